@@ -0,0 +1,799 @@
+use std::{
+    error::Error,
+    f64::consts::PI,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use castep_model_generator_backend::{
+    atom::{Atom, AtomArray},
+    lattice::Lattice,
+    parser::msi_parser::parse_lattice,
+    Export,
+};
+use glob::glob;
+use indicatif::ProgressBar;
+use nalgebra::{Point3, Rotation3, Unit, Vector3};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    editor::gdy_tri_editor::MissingAtomIdError,
+    failure_report::{FailureReport, ModelFailure},
+    manifest::{
+        site_elements, summary, validation_report, write_manifest, write_validated, ManifestEntry,
+        WriteOptions,
+    },
+    project_config::{CoordCase, GdyProject},
+    seed_export::{species_list, SeedExporter},
+    symmetry::PeriodicCell,
+    validation::model_issues,
+};
+
+/**
+Define Structs to deserialize `ads_table.yaml`.
+Atom ids in the table refer to the atoms of the adsorbate model `{ads_model_loc}/{pathName}/{name}.msi`.
+*/
+#[derive(Deserialize, Debug)]
+pub struct AdsTable {
+    directory: String,
+    #[serde(rename = "Adsorbates")]
+    adsorbates: Vec<AdsInfo>,
+}
+
+impl AdsTable {
+    /// Directory under `export_loc` receiving the adsorption models.
+    pub fn directory(&self) -> &str {
+        self.directory.as_ref()
+    }
+
+    pub fn adsorbates(&self) -> &[AdsInfo] {
+        self.adsorbates.as_ref()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AdsInfo {
+    name: String,
+    coord_atom_ids: Vec<u32>,
+    stem_atom_ids: Vec<u32>,
+    plane_atom_ids: Vec<u32>,
+    vertical: bool,
+    b_sym: bool,
+    upper_atom_id: u32,
+    atom_nums: u32,
+    path_name: String,
+}
+
+impl AdsInfo {
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn coord_atom_ids(&self) -> &[u32] {
+        self.coord_atom_ids.as_ref()
+    }
+
+    pub fn stem_atom_ids(&self) -> &[u32] {
+        self.stem_atom_ids.as_ref()
+    }
+
+    pub fn plane_atom_ids(&self) -> &[u32] {
+        self.plane_atom_ids.as_ref()
+    }
+
+    pub fn vertical(&self) -> bool {
+        self.vertical
+    }
+
+    /// Symmetric adsorbate: swapping its two coordinating atoms gives the same model.
+    pub fn b_sym(&self) -> bool {
+        self.b_sym
+    }
+
+    pub fn upper_atom_id(&self) -> u32 {
+        self.upper_atom_id
+    }
+
+    pub fn atom_nums(&self) -> u32 {
+        self.atom_nums
+    }
+
+    pub fn path_name(&self) -> &str {
+        self.path_name.as_ref()
+    }
+
+    /// `coordAtomIds` holds one or two atoms and `stemAtomIds` two, the ends of the stem.
+    pub fn validate(&self) -> Result<(), InvalidAdsorbateError> {
+        if !(1..=2).contains(&self.coord_atom_ids.len()) {
+            return Err(InvalidAdsorbateError::new(
+                &self.name,
+                "coordAtomIds needs one or two atom ids",
+            ));
+        }
+        if self.stem_atom_ids.len() < 2 {
+            return Err(InvalidAdsorbateError::new(
+                &self.name,
+                "stemAtomIds needs two atom ids, the ends of the stem",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An adsorbate of the table that cannot be placed.
+#[derive(Debug, Clone)]
+pub struct InvalidAdsorbateError {
+    name: String,
+    reason: String,
+}
+
+impl InvalidAdsorbateError {
+    pub fn new(name: &str, reason: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for InvalidAdsorbateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Adsorbate {}: {}", self.name, self.reason)
+    }
+}
+
+impl Error for InvalidAdsorbateError {}
+
+/// Load `ads_table.yaml`, rejecting adsorbates whose atom ids cannot define a placement.
+pub fn load_ads_table(filename: &str) -> Result<AdsTable, Box<dyn Error>> {
+    let text = fs::read_to_string(filename)?;
+    let table: AdsTable = serde_yaml::from_str(&text)?;
+    for ads_info in table.adsorbates() {
+        ads_info.validate()?;
+    }
+    Ok(table)
+}
+
+/// Settings of the adsorbate placement.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PlacementSettings {
+    /// Height of the coordinating atom(s) above the site(s), in Angstrom.
+    height: f64,
+    /// Angular step of the search of the orientation around the stem, in degrees.
+    roll_step: f64,
+}
+
+impl Default for PlacementSettings {
+    fn default() -> Self {
+        Self {
+            height: 2.0,
+            roll_step: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InvalidPlacementError(pub String);
+
+impl Display for InvalidPlacementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid placement settings: {}", self.0)
+    }
+}
+
+impl Error for InvalidPlacementError {}
+
+impl PlacementSettings {
+    /// `roll_step` must be a positive angle, or the roll search never ends.
+    pub fn validate(&self) -> Result<(), InvalidPlacementError> {
+        if !(self.roll_step > 0.0 && self.roll_step.is_finite()) {
+            return Err(InvalidPlacementError(format!(
+                "roll_step must be a positive number of degrees, got {}",
+                self.roll_step
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn height(&self) -> f64 {
+        self.height
+    }
+
+    pub fn roll_step(&self) -> f64 {
+        self.roll_step
+    }
+}
+
+fn molecule_position(molecule: &Lattice, atom_id: u32) -> Result<Vector3<f64>, MissingAtomIdError> {
+    molecule
+        .atoms_vec()
+        .get_atom_by_id(atom_id)
+        .map(|atom| atom.xyz().coords)
+        .ok_or_else(|| MissingAtomIdError::new(atom_id, molecule.lattice_name()))
+}
+
+/// Unit normal of the plane through three atoms, `None` when they are collinear.
+fn plane_normal(positions: &[Vector3<f64>]) -> Option<Vector3<f64>> {
+    if positions.len() < 3 {
+        return None;
+    }
+    let normal = (positions[1] - positions[0]).cross(&(positions[2] - positions[0]));
+    if normal.norm() < 1e-6 {
+        None
+    } else {
+        Some(normal.normalize())
+    }
+}
+
+/// Rotation taking `from` onto `to`, including the antiparallel case.
+fn align(from: &Vector3<f64>, to: &Vector3<f64>) -> Rotation3<f64> {
+    Rotation3::rotation_between(from, to).unwrap_or_else(|| {
+        // `from` and `to` are antiparallel: turn by PI around any perpendicular axis.
+        let helper = if from.x.abs() < 0.9 {
+            Vector3::x()
+        } else {
+            Vector3::y()
+        };
+        Rotation3::from_axis_angle(&Unit::new_normalize(from.cross(&helper)), PI)
+    })
+}
+
+/**
+Orient and translate the adsorbate onto one coordination case.
+1. The primary axis is aligned: the vector between the two coordinating atoms goes
+   along the bridge for a double case; otherwise the stem, from the coordinating atoms
+   towards its farther end, goes along the surface normal (`vertical`) or along the bridge / the `a` axis.
+2. The molecule is rolled around that axis so that its plane is perpendicular to the
+   surface (`vertical`) or parallel to it, then with `upperAtomId` as high as possible,
+   then with the plane containing the bridge.
+3. The coordinating atoms are centred `height` above the site, or the bridge centre.
+# Returns:
+- Cartesian positions of the molecule atoms, in the order of `atoms_vec`.
+- Error for invalid adsorbate or placement settings, or a bridge whose sites are stacked along z.
+*/
+pub fn place_molecule(
+    molecule: &Lattice,
+    ads_info: &AdsInfo,
+    site_positions: &[Vector3<f64>],
+    settings: &PlacementSettings,
+) -> Result<Vec<Vector3<f64>>, Box<dyn Error>> {
+    ads_info.validate()?;
+    settings.validate()?;
+    let invalid = |reason: &str| InvalidAdsorbateError::new(ads_info.name(), reason);
+    if site_positions.is_empty() {
+        return Err(Box::new(invalid("no site to place it on")));
+    }
+    let normal = Vector3::z();
+    let coord_positions = ads_info
+        .coord_atom_ids()
+        .iter()
+        .map(|&id| molecule_position(molecule, id))
+        .collect::<Result<Vec<Vector3<f64>>, MissingAtomIdError>>()?;
+    let coord_center: Vector3<f64> =
+        coord_positions.iter().sum::<Vector3<f64>>() / coord_positions.len() as f64;
+    let bridge: Option<Vector3<f64>> = match site_positions {
+        [first, second] => {
+            let b = second - first;
+            let b = Vector3::new(b.x, b.y, 0.0);
+            if b.norm() < 1e-6 {
+                return Err(Box::new(invalid(
+                    "the two bridge sites share their x, y position",
+                )));
+            }
+            Some(b.normalize())
+        }
+        _ => None,
+    };
+    let in_plane_dir = bridge.unwrap_or_else(Vector3::x);
+    let (primary, target) = if coord_positions.len() == 2 && bridge.is_some() {
+        (coord_positions[1] - coord_positions[0], in_plane_dir)
+    } else {
+        let (first, second) = match ads_info.stem_atom_ids() {
+            [first, second, ..] => (*first, *second),
+            _ => {
+                return Err(Box::new(invalid(
+                    "stemAtomIds needs two atom ids, the ends of the stem",
+                )))
+            }
+        };
+        let first = molecule_position(molecule, first)?;
+        let second = molecule_position(molecule, second)?;
+        // The stem points from the coordinating atoms towards its farther end.
+        let far_end = if (first - coord_center).norm() > (second - coord_center).norm() + 1e-6 {
+            first
+        } else {
+            second
+        };
+        let stem = if (far_end - coord_center).norm() < 1e-6 {
+            second - first
+        } else {
+            far_end - coord_center
+        };
+        let target = if ads_info.vertical() {
+            normal
+        } else {
+            in_plane_dir
+        };
+        (stem, target)
+    };
+    let centered: Vec<Vector3<f64>> = molecule
+        .atoms_vec()
+        .iter()
+        .map(|atom| atom.xyz().coords - coord_center)
+        .collect();
+    let first_rotation = if primary.norm() < 1e-6 {
+        Rotation3::identity()
+    } else {
+        align(&primary, &target)
+    };
+    let aligned: Vec<Vector3<f64>> = centered.iter().map(|v| first_rotation * v).collect();
+    let index_of = |atom_id: u32| -> Option<usize> {
+        molecule
+            .atoms_vec()
+            .iter()
+            .position(|atom| atom.atom_id() == atom_id)
+    };
+    let plane_ids: Vec<usize> = ads_info
+        .plane_atom_ids()
+        .iter()
+        .filter_map(|&id| index_of(id))
+        .collect();
+    let upper_idx = index_of(ads_info.upper_atom_id()).ok_or_else(|| {
+        MissingAtomIdError::new(ads_info.upper_atom_id(), molecule.lattice_name())
+    })?;
+    let axis = Unit::new_normalize(target);
+    let num_steps = (360.0 / settings.roll_step()).round().max(1.0) as usize;
+    // (plane score, upper atom height, bridge score) of each roll angle
+    let candidates: Vec<(Rotation3<f64>, f64, f64, f64)> = (0..num_steps)
+        .map(|step| {
+            let roll = Rotation3::from_axis_angle(&axis, step as f64 * 2.0 * PI / num_steps as f64);
+            let plane_positions: Vec<Vector3<f64>> =
+                plane_ids.iter().map(|&i| roll * aligned[i]).collect();
+            let (plane_score, bridge_score) = match plane_normal(&plane_positions) {
+                Some(n) => {
+                    let plane_score = if ads_info.vertical() {
+                        1.0 - n.dot(&normal).abs()
+                    } else {
+                        n.dot(&normal).abs()
+                    };
+                    (plane_score, 1.0 - n.dot(&in_plane_dir).abs())
+                }
+                None => (0.0, 0.0),
+            };
+            (
+                roll,
+                plane_score,
+                (roll * aligned[upper_idx]).z,
+                bridge_score,
+            )
+        })
+        .collect();
+    let tol = 1e-3;
+    let best_plane = candidates
+        .iter()
+        .map(|c| c.1)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let best_upper = candidates
+        .iter()
+        .filter(|c| c.1 >= best_plane - tol)
+        .map(|c| c.2)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let best = candidates
+        .iter()
+        .filter(|c| c.1 >= best_plane - tol && c.2 >= best_upper - tol)
+        .max_by(|a, b| a.3.total_cmp(&b.3))
+        .ok_or_else(|| invalid("no orientation found around the stem"))?;
+    let anchor: Vector3<f64> = site_positions.iter().sum::<Vector3<f64>>()
+        / site_positions.len() as f64
+        + normal * settings.height();
+    Ok(aligned.iter().map(|v| best.0 * v + anchor).collect())
+}
+
+/// A coordination case resolved against the lattice: name, and the site atom ids in order.
+#[derive(Debug, Clone)]
+pub struct AdsSite {
+    name: String,
+    atom_ids: Vec<u32>,
+}
+
+impl AdsSite {
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn atom_ids(&self) -> &[u32] {
+        self.atom_ids.as_ref()
+    }
+}
+
+/**
+All placements of an adsorbate over the coordination cases of the project.
+Adsorbates with one coordinating atom go on single sites and bridges; adsorbates
+with two coordinating atoms go on double cases only, in both directions unless `bSym`.
+*/
+pub fn ads_sites(ads_info: &AdsInfo, gdy_project: &GdyProject) -> Vec<AdsSite> {
+    let site_name = |atom_id: u32| -> String {
+        gdy_project
+            .coord_sites()
+            .iter()
+            .find(|site| site.atom_id() == atom_id)
+            .map(|site| site.name().to_string())
+            .unwrap_or_else(|| atom_id.to_string())
+    };
+    let resolve = |ids: Vec<u32>| -> AdsSite {
+        AdsSite {
+            name: ids
+                .iter()
+                .map(|&id| site_name(id))
+                .collect::<Vec<String>>()
+                .join("_"),
+            atom_ids: ids,
+        }
+    };
+    let two_coords = ads_info.coord_atom_ids().len() == 2;
+    gdy_project
+        .coord_cases()
+        .iter()
+        .flat_map(|cases: &CoordCase| cases.cases().to_vec())
+        .flat_map(|(a, b)| -> Vec<AdsSite> {
+            match b {
+                None if two_coords => vec![],
+                None => vec![resolve(vec![a])],
+                Some(b) if two_coords && !ads_info.b_sym() => {
+                    vec![resolve(vec![a, b]), resolve(vec![b, a])]
+                }
+                Some(b) => vec![resolve(vec![a, b])],
+            }
+        })
+        .collect()
+}
+
+/// Copy of `lattice` with the adsorbate placed on `site`, named `{lattice}_{ads}_{site}`.
+pub fn add_adsorbate(
+    lattice: &Lattice,
+    molecule: &Lattice,
+    ads_info: &AdsInfo,
+    site: &AdsSite,
+    settings: &PlacementSettings,
+) -> Result<Lattice, Box<dyn Error>> {
+    let cell = PeriodicCell::from_lattice(lattice)?;
+    let first_site = lattice
+        .atoms_vec()
+        .get_atom_by_id(site.atom_ids()[0])
+        .ok_or_else(|| MissingAtomIdError::new(site.atom_ids()[0], lattice.lattice_name()))?
+        .xyz()
+        .coords;
+    // Positions of bridge partners are taken from the image closest to the first site.
+    let site_positions = site
+        .atom_ids()
+        .iter()
+        .map(|&id| {
+            lattice
+                .atoms_vec()
+                .get_atom_by_id(id)
+                .map(|atom| first_site + cell.minimum_image_vector(&first_site, &atom.xyz().coords))
+                .ok_or_else(|| MissingAtomIdError::new(id, lattice.lattice_name()))
+        })
+        .collect::<Result<Vec<Vector3<f64>>, MissingAtomIdError>>()?;
+    let positions = place_molecule(molecule, ads_info, &site_positions, settings)?;
+    let mut new_lattice = lattice.clone();
+    let last_id = lattice
+        .atoms_vec()
+        .iter()
+        .map(|atom| atom.atom_id())
+        .max()
+        .unwrap_or(0);
+    molecule
+        .atoms_vec()
+        .iter()
+        .zip(positions.iter())
+        .enumerate()
+        .for_each(|(i, (atom, xyz))| {
+            new_lattice.atoms_vec_mut().push(Atom::new(
+                atom.element_name().to_string(),
+                atom.element_id(),
+                Point3::from(*xyz),
+                last_id + i as u32 + 1,
+            ))
+        });
+    new_lattice.set_lattice_name(ads_model_name(lattice.lattice_name(), ads_info, site));
+    Ok(new_lattice)
+}
+
+/**
+Place every adsorbate of the table on every coordination case of `lattice`.
+Models are written to `{target_root_dir}/{directory}/{pathName}/{lattice}/{seed_dir}/`.
+# Returns:
+- One result per model; a failed model does not stop the others.
+*/
+pub fn generate_ads_models(
+    lattice: &Lattice,
+    target_root_dir: &str,
+    ads_table: &AdsTable,
+    molecules: &[Lattice],
+    gdy_project: &GdyProject,
+    seed_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Vec<Result<ManifestEntry, ModelFailure>> {
+    let mut results: Vec<Result<ManifestEntry, ModelFailure>> = vec![];
+    for (ads_info, molecule) in ads_table.adsorbates().iter().zip(molecules.iter()) {
+        let dir: PathBuf = [
+            target_root_dir,
+            ads_table.directory(),
+            ads_info.path_name(),
+            lattice.lattice_name(),
+        ]
+        .iter()
+        .collect();
+        for site in ads_sites(ads_info, gdy_project).iter() {
+            let result = add_adsorbate(lattice, molecule, ads_info, site, gdy_project.placement())
+                .and_then(|ads_lattice| {
+                    write_ads_model(
+                        &ads_lattice,
+                        lattice,
+                        &dir,
+                        gdy_project,
+                        seed_exporter,
+                        options,
+                    )
+                })
+                .map_err(|e| {
+                    ModelFailure::new(&ads_model_name(lattice.lattice_name(), ads_info, site), e)
+                });
+            results.push(result);
+        }
+    }
+    results
+}
+
+/// `{lattice}_{ads}_{site}`
+pub fn ads_model_name(lattice_name: &str, ads_info: &AdsInfo, site: &AdsSite) -> String {
+    format!("{}_{}_{}", lattice_name, ads_info.name(), site.name())
+}
+
+/// Validate and write one adsorbate model of `base_lattice` with its seed files.
+fn write_ads_model(
+    ads_lattice: &Lattice,
+    base_lattice: &Lattice,
+    dir: &Path,
+    gdy_project: &GdyProject,
+    seed_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Result<ManifestEntry, Box<dyn Error>> {
+    let name = ads_lattice.lattice_name();
+    let seed_dir = dir.join(gdy_project.naming().seed_dir(name));
+    let filepath = seed_dir.join(format!("{}.msi", name));
+    let seed_files = seed_exporter.seed_files(ads_lattice, &seed_dir)?;
+    let validation = gdy_project.validation();
+    let issues = model_issues(ads_lattice, validation)?;
+    let (status, hash) = write_validated(
+        &filepath,
+        &ads_lattice.format_output(),
+        &seed_files,
+        &issues,
+        validation.skip_invalid(),
+        options,
+    )?;
+    if status.is_written() && !options.dry_run() {
+        seed_exporter.write_seed_files(ads_lattice, &seed_dir, &seed_files)?;
+    }
+    Ok(ManifestEntry::new(
+        name,
+        site_elements(base_lattice, gdy_project.substitution_sites()),
+        &filepath,
+        hash,
+        status,
+        issues,
+    ))
+}
+
+/// Load the adsorbate models listed in the table, aligned with `ads_table.adsorbates()`.
+pub fn load_ads_models(
+    ads_table: &AdsTable,
+    ads_model_loc: &str,
+) -> Result<Vec<Lattice>, Box<dyn Error>> {
+    ads_table
+        .adsorbates()
+        .iter()
+        .map(|ads_info| {
+            let path: PathBuf = [
+                ads_model_loc,
+                ads_info.path_name(),
+                &format!("{}.msi", ads_info.name()),
+            ]
+            .iter()
+            .collect();
+            parse_lattice(path.to_str().unwrap())
+        })
+        .collect()
+}
+
+/// Generated base models: `{target_root_dir}/{family}/{element}/{seed_dir}/{name}.msi`.
+pub fn find_base_models(target_root_dir: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let pattern = format!("{}/*/*/*/*.msi", target_root_dir);
+    Ok(glob(&pattern)?.filter_map(|path| path.ok()).collect())
+}
+
+/**
+Place all adsorbates on the `base_models`, e.g. from `find_base_models(target_root_dir)`.
+# Returns:
+- The models left out of the run, including the base models that could not be read.
+*/
+pub fn generate_all_ads_models(
+    base_models: &[PathBuf],
+    target_root_dir: &str,
+    ads_table_loc: &str,
+    gdy_project: &GdyProject,
+    seed_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let ads_table = load_ads_table(ads_table_loc)?;
+    let molecules = load_ads_models(&ads_table, gdy_project.ads_model_loc())?;
+    let mut symbols: Vec<&str> = vec![];
+    molecules
+        .iter()
+        .flat_map(|molecule| species_list(molecule))
+        .for_each(|elm| {
+            if !symbols.contains(&elm) {
+                symbols.push(elm)
+            }
+        });
+    seed_exporter.check_potentials(&symbols)?;
+    let bar = ProgressBar::new(base_models.len() as u64);
+    let results: Vec<Result<ManifestEntry, ModelFailure>> = base_models
+        .par_iter()
+        .flat_map(|path| {
+            let results = match parse_lattice(&path.to_string_lossy()) {
+                Ok(lattice) => generate_ads_models(
+                    &lattice,
+                    target_root_dir,
+                    &ads_table,
+                    &molecules,
+                    gdy_project,
+                    seed_exporter,
+                    options,
+                ),
+                Err(e) => vec![Err(ModelFailure::new(
+                    &path.file_stem().unwrap_or_default().to_string_lossy(),
+                    e,
+                ))],
+            };
+            bar.inc(1);
+            results
+        })
+        .collect();
+    bar.finish();
+    let (entries, report) = FailureReport::collect(results);
+    validation_report(&entries)
+        .iter()
+        .for_each(|line| println!("{}", line));
+    if options.dry_run() {
+        println!("{}", summary(&entries, options));
+        return Ok(report);
+    }
+    write_manifest(
+        &Path::new(target_root_dir).join(ads_table.directory()),
+        "manifest",
+        &entries,
+    )?;
+    println!("{}", summary(&entries, options));
+    Ok(report)
+}
+
+#[cfg(test)]
+#[test]
+fn test_ads_sites() {
+    use crate::project_config::load_gdy_project;
+
+    let gdy_project = load_gdy_project("./resources/project.yaml").unwrap();
+    let ads_table = load_ads_table("./resources/ads_table.yaml").unwrap();
+    let sites_of = |name: &str| -> Vec<AdsSite> {
+        let ads_info = ads_table
+            .adsorbates()
+            .iter()
+            .find(|ads| ads.name() == name)
+            .unwrap();
+        ads_sites(ads_info, &gdy_project)
+    };
+    let co_sites = sites_of("CO");
+    assert_eq!(co_sites.len(), 14);
+    assert_eq!(co_sites[0].name(), "c1_c2");
+    assert_eq!(co_sites[7].name(), "c1");
+    assert_eq!(sites_of("OCH2CO").len(), 14);
+    assert_eq!(sites_of("ethylene_glycol").len(), 7);
+}
+
+#[cfg(test)]
+#[test]
+fn test_place_molecule() {
+    use crate::structure_import::build_lattice;
+    use nalgebra::Matrix3;
+
+    let ads_table = load_ads_table("./resources/ads_table.yaml").unwrap();
+    let ads_info = |name: &str| -> &AdsInfo {
+        ads_table
+            .adsorbates()
+            .iter()
+            .find(|ads| ads.name() == name)
+            .unwrap()
+    };
+    let molecule = |name: &str, atoms: &[(&str, [f64; 3])]| -> Lattice {
+        build_lattice(
+            name,
+            Matrix3::identity() * 20.0,
+            atoms
+                .iter()
+                .map(|(elm, xyz)| (elm.to_string(), Point3::from(*xyz)))
+                .collect(),
+        )
+        .unwrap()
+    };
+    let settings = PlacementSettings::default();
+    // Heights of the placed atoms; the coordinating atoms must be the lowest.
+    let heights = |ads: &str, lattice: &Lattice, sites: &[Vector3<f64>]| -> Vec<f64> {
+        place_molecule(lattice, ads_info(ads), sites, &settings)
+            .unwrap()
+            .iter()
+            .map(|xyz| xyz.z)
+            .collect()
+    };
+    let lowest = |z: &[f64]| z.iter().copied().fold(f64::INFINITY, f64::min);
+    let single_site = [Vector3::new(0.0, 0.0, 5.0)];
+    let co = molecule("CO", &[("C", [0.0, 0.0, 0.0]), ("O", [1.13, 0.0, 0.0])]);
+    let z = heights("CO", &co, &single_site);
+    assert!((z[0] - 7.0).abs() < 1e-6);
+    assert!(z[1] > z[0] + 1.0);
+    // COCHO coordinates through atom 3, the second stem atom.
+    let cocho = molecule(
+        "COCHO",
+        &[
+            ("C", [2.4, 0.0, 0.0]),
+            ("C", [1.2, 0.7, 0.0]),
+            ("C", [0.0, 0.0, 0.0]),
+            ("O", [3.5, 0.6, 0.0]),
+            ("H", [1.2, 1.8, 0.0]),
+        ],
+    );
+    let z = heights("COCHO", &cocho, &single_site);
+    assert!((z[2] - 7.0).abs() < 1e-6);
+    assert!((lowest(&z) - z[2]).abs() < 1e-6);
+    // OCH2CO bridges two sites through atoms 1 and 3.
+    let och2co = molecule(
+        "OCH2CO",
+        &[
+            ("O", [0.0, 0.0, 0.0]),
+            ("C", [0.7, 1.0, 0.0]),
+            ("C", [1.4, 0.0, 0.0]),
+            ("H", [0.2, 1.8, 0.0]),
+            ("H", [1.2, 1.8, 0.0]),
+            ("O", [2.4, 0.6, 0.0]),
+        ],
+    );
+    let bridge = [Vector3::new(0.0, 0.0, 5.0), Vector3::new(2.6, 0.0, 5.0)];
+    let z = heights("OCH2CO", &och2co, &bridge);
+    assert!((z[0] - 7.0).abs() < 1e-6 && (z[2] - 7.0).abs() < 1e-6);
+    assert!((lowest(&z) - 7.0).abs() < 1e-6);
+
+    let mut no_stem = ads_info("COCHO").clone();
+    no_stem.stem_atom_ids = vec![3];
+    let error = place_molecule(&cocho, &no_stem, &single_site, &settings).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Adsorbate COCHO: stemAtomIds needs two atom ids, the ends of the stem"
+    );
+    let mut no_coord = ads_info("CO").clone();
+    no_coord.coord_atom_ids = vec![];
+    assert!(place_molecule(&co, &no_coord, &single_site, &settings).is_err());
+    let stacked = [Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 8.0)];
+    assert!(place_molecule(&och2co, ads_info("OCH2CO"), &stacked, &settings).is_err());
+    assert!(place_molecule(&co, ads_info("CO"), &[], &settings).is_err());
+    for roll_step in ["0", "-1", ".nan"] {
+        let settings: PlacementSettings =
+            serde_yaml::from_str(&format!("{{height: 2.0, roll_step: {}}}", roll_step)).unwrap();
+        assert!(settings.validate().is_err());
+        assert!(place_molecule(&co, ads_info("CO"), &single_site, &settings).is_err());
+    }
+}
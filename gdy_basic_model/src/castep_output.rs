@@ -0,0 +1,561 @@
+use std::{error::Error, fmt::Display, fs, path::Path};
+
+use castep_model_generator_backend::lattice::Lattice;
+use nalgebra::{Matrix3, Point3, Vector3};
+
+use crate::structure_import::build_lattice;
+
+#[derive(Debug, Clone)]
+pub struct ParseCastepError(pub String);
+
+impl Display for ParseCastepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse .castep output: {}", self.0)
+    }
+}
+
+impl Error for ParseCastepError {}
+
+/// (element, position) of each atom.
+type Positions = Vec<(String, Point3<f64>)>;
+
+/// Outcome of the calculation according to the final messages of the `.castep`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Convergence {
+    /// `Geometry optimization completed successfully`, or a finished single point run.
+    Converged,
+    /// `Geometry optimization failed to converge after N steps`.
+    MaxIterations,
+    /// No final message: the run is still going or has been killed.
+    Incomplete,
+}
+
+/// Charge and spin of one atom from the Mulliken or Hirshfeld analysis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtomPopulation {
+    species: String,
+    ion: u32,
+    /// Charge in e.
+    charge: f64,
+    /// Spin in hbar/2; `None` for spin-unpolarized runs.
+    spin: Option<f64>,
+}
+
+impl AtomPopulation {
+    pub fn species(&self) -> &str {
+        self.species.as_ref()
+    }
+
+    pub fn ion(&self) -> u32 {
+        self.ion
+    }
+
+    pub fn charge(&self) -> f64 {
+        self.charge
+    }
+
+    pub fn spin(&self) -> Option<f64> {
+        self.spin
+    }
+}
+
+/**
+Results and metadata of one `.castep` output.
+When the file holds several runs (continuations), only the values of the last one are kept,
+even when it has not finished; energies are in eV.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastepOutput {
+    task: Option<String>,
+    /// Last `Final energy`.
+    final_energy: Option<f64>,
+    /// Last `Final free energy (E-TS)`.
+    final_free_energy: Option<f64>,
+    /// `Final Enthalpy` of the geometry optimization.
+    final_enthalpy: Option<f64>,
+    /// Last `finished iteration` of the geometry optimization.
+    geom_steps: u32,
+    convergence: Convergence,
+    /// Distinct warning lines of the last run, in order of appearance.
+    warnings: Vec<String>,
+    /// The SCF of the last block, after the last geometry step, hit the maximum number of cycles.
+    scf_failed: bool,
+    mulliken: Vec<AtomPopulation>,
+    hirshfeld: Vec<AtomPopulation>,
+    /// Plane wave cut-off in eV.
+    cut_off_energy: Option<f64>,
+    /// Monkhorst-Pack grid of the SCF k-points.
+    kpoint_grid: Option<[u32; 3]>,
+    /// Last `Real Lattice(A)`, vectors as columns.
+    cell: Option<Matrix3<f64>>,
+    /// Last `Cell Contents`: (element, fractional position).
+    positions: Positions,
+}
+
+impl CastepOutput {
+    pub fn task(&self) -> Option<&str> {
+        self.task.as_deref()
+    }
+
+    pub fn final_energy(&self) -> Option<f64> {
+        self.final_energy
+    }
+
+    pub fn final_free_energy(&self) -> Option<f64> {
+        self.final_free_energy
+    }
+
+    pub fn final_enthalpy(&self) -> Option<f64> {
+        self.final_enthalpy
+    }
+
+    pub fn geom_steps(&self) -> u32 {
+        self.geom_steps
+    }
+
+    pub fn convergence(&self) -> Convergence {
+        self.convergence
+    }
+
+    pub fn is_converged(&self) -> bool {
+        self.convergence == Convergence::Converged
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        self.warnings.as_ref()
+    }
+
+    /**
+    The last SCF block reached the maximum number of cycles without converging.
+    A warning followed by a finished geometry step does not count: the optimization went on.
+    */
+    pub fn scf_failed(&self) -> bool {
+        self.scf_failed
+    }
+
+    pub fn mulliken(&self) -> &[AtomPopulation] {
+        self.mulliken.as_ref()
+    }
+
+    pub fn hirshfeld(&self) -> &[AtomPopulation] {
+        self.hirshfeld.as_ref()
+    }
+
+    pub fn cut_off_energy(&self) -> Option<f64> {
+        self.cut_off_energy
+    }
+
+    pub fn kpoint_grid(&self) -> Option<[u32; 3]> {
+        self.kpoint_grid
+    }
+
+    pub fn cell(&self) -> Option<&Matrix3<f64>> {
+        self.cell.as_ref()
+    }
+
+    pub fn positions(&self) -> &[(String, Point3<f64>)] {
+        self.positions.as_ref()
+    }
+
+    /// Last geometry of the run, with atom ids 1..N in the order of `Cell Contents`.
+    pub fn final_lattice(&self, name: &str) -> Result<Option<Lattice>, Box<dyn Error>> {
+        match self.cell {
+            Some(cell) if !self.positions.is_empty() => {
+                let sites: Vec<(String, Point3<f64>)> = self
+                    .positions
+                    .iter()
+                    .map(|(element, frac)| (element.clone(), Point3::from(cell * frac.coords)))
+                    .collect();
+                Ok(Some(build_lattice(name, cell, sites)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Enthalpy of a geometry optimization, else the final energy.
+    pub fn energy(&self) -> Option<f64> {
+        self.final_enthalpy.or(self.final_energy)
+    }
+}
+
+pub fn read_castep<P: AsRef<Path>>(path: P) -> Result<CastepOutput, Box<dyn Error>> {
+    Ok(parse_castep(&fs::read_to_string(path)?)?)
+}
+
+/// First number after `=` or `:` in `line`.
+fn value_after(line: &str, separator: char) -> Result<f64, ParseCastepError> {
+    line.split_once(separator)
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .and_then(|value| value.parse::<f64>().ok())
+        .ok_or_else(|| ParseCastepError(format!("no value in line '{}'", line.trim())))
+}
+
+/// CASTEP warning lines start with `*Warning*` or `Warning:`; other mentions of the word are not warnings.
+fn is_warning(trimmed: &str) -> bool {
+    ["*warning*", "warning:"].iter().any(|prefix| {
+        trimmed
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    })
+}
+
+/**
+Parse the text of a `.castep` output.
+# Returns:
+- Missing entries are left as `None`/empty; an error only for a malformed entry.
+*/
+pub fn parse_castep(text: &str) -> Result<CastepOutput, ParseCastepError> {
+    let empty = CastepOutput {
+        task: None,
+        final_energy: None,
+        final_free_energy: None,
+        final_enthalpy: None,
+        geom_steps: 0,
+        convergence: Convergence::Incomplete,
+        warnings: vec![],
+        scf_failed: false,
+        mulliken: vec![],
+        hirshfeld: vec![],
+        cut_off_energy: None,
+        kpoint_grid: None,
+        cell: None,
+        positions: vec![],
+    };
+    let mut output = empty.clone();
+    let mut finished = false;
+    let lines: Vec<&str> = text.lines().collect();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+        if trimmed.starts_with("type of calculation") {
+            // A new run starts: only its own results count.
+            output = CastepOutput {
+                task: trimmed
+                    .split_once(':')
+                    .map(|(_, task)| task.trim().to_string()),
+                ..empty.clone()
+            };
+            finished = false;
+        } else if trimmed.starts_with("Final energy") {
+            output.final_energy = Some(value_after(trimmed, '=')?);
+        } else if trimmed.starts_with("Final free energy") {
+            output.final_free_energy = Some(value_after(trimmed, '=')?);
+        } else if trimmed.contains("Final Enthalpy") {
+            output.final_enthalpy = Some(value_after(trimmed, '=')?);
+        } else if trimmed.contains(": finished iteration") {
+            output.geom_steps = trimmed
+                .split_whitespace()
+                .skip_while(|&word| word != "iteration")
+                .nth(1)
+                .and_then(|step| step.parse::<u32>().ok())
+                .ok_or_else(|| ParseCastepError(format!("no step in line '{}'", trimmed)))?;
+            output.scf_failed = false;
+        } else if trimmed.contains("Geometry optimization completed successfully") {
+            output.convergence = Convergence::Converged;
+        } else if trimmed.contains("Geometry optimization failed to converge") {
+            output.convergence = Convergence::MaxIterations;
+        } else if trimmed.starts_with("Total time") {
+            finished = true;
+        } else if trimmed.starts_with("plane wave basis set cut-off") {
+            output.cut_off_energy = Some(value_after(trimmed, ':')?);
+        } else if trimmed.starts_with("MP grid size for SCF calculation is") {
+            let grid: Vec<u32> = trimmed
+                .trim_start_matches("MP grid size for SCF calculation is")
+                .split_whitespace()
+                .filter_map(|n| n.parse::<u32>().ok())
+                .collect();
+            output.kpoint_grid = match grid[..] {
+                [a, b, c] => Some([a, b, c]),
+                _ => return Err(ParseCastepError(format!("bad k-point grid '{}'", trimmed))),
+            };
+        } else if is_warning(trimmed) {
+            if trimmed.contains("max. SCF cycles performed") {
+                output.scf_failed = true;
+            }
+            let warning = trimmed.to_string();
+            if !output.warnings.contains(&warning) {
+                output.warnings.push(warning)
+            }
+        } else if trimmed.starts_with("Atomic Populations (Mulliken)") {
+            let (populations, end) = parse_population_table(&lines, i, 5)?;
+            output.mulliken = populations;
+            i = end;
+        } else if trimmed.starts_with("Hirshfeld Analysis") {
+            let (populations, end) = parse_population_table(&lines, i, 0)?;
+            output.hirshfeld = populations;
+            i = end;
+        } else if trimmed.starts_with("Real Lattice(A)") {
+            let vectors = lines
+                .iter()
+                .skip(i + 1)
+                .take(3)
+                .map(|line| {
+                    let values: Vec<f64> = line
+                        .split_whitespace()
+                        .take(3)
+                        .filter_map(|value| value.parse::<f64>().ok())
+                        .collect();
+                    match values[..] {
+                        [x, y, z] => Ok(Vector3::new(x, y, z)),
+                        _ => Err(ParseCastepError(format!(
+                            "bad lattice vector '{}'",
+                            line.trim()
+                        ))),
+                    }
+                })
+                .collect::<Result<Vec<Vector3<f64>>, ParseCastepError>>()?;
+            if vectors.len() != 3 {
+                return Err(ParseCastepError("truncated Real Lattice".to_string()));
+            }
+            output.cell = Some(Matrix3::from_columns(&vectors));
+            i += 3;
+        } else if trimmed.contains("Fractional coordinates of atoms") {
+            let (positions, end) = parse_cell_contents(&lines, i)?;
+            output.positions = positions;
+            i = end;
+        }
+        i += 1;
+    }
+    let geometry = output
+        .task
+        .as_deref()
+        .map(|task| task.contains("geometry"))
+        .unwrap_or(false);
+    if finished && !geometry && output.final_energy.is_some() {
+        output.convergence = Convergence::Converged;
+    }
+    Ok(output)
+}
+
+/**
+Read the `x  Element  Atom  u  v  w  x` rows of the `Cell Contents` table titled at `lines[start]`.
+Species labels such as `Fe:1` are reduced to the element.
+# Returns:
+- (element and fractional position of each atom, index of the closing `xxxx` line)
+*/
+fn parse_cell_contents(
+    lines: &[&str],
+    start: usize,
+) -> Result<(Positions, usize), ParseCastepError> {
+    let mut positions: Vec<(String, Point3<f64>)> = vec![];
+    let mut in_rows = false;
+    for (idx, line) in lines.iter().enumerate().skip(start + 1) {
+        let trimmed = line.trim();
+        if trimmed.starts_with("x---") {
+            in_rows = true;
+        } else if trimmed.starts_with("xxxx") {
+            return Ok((positions, idx));
+        } else if in_rows {
+            let words: Vec<&str> = trimmed.trim_matches('x').split_whitespace().collect();
+            let bad_row = || ParseCastepError(format!("bad cell contents row '{}'", trimmed));
+            if words.len() < 5 {
+                return Err(bad_row());
+            }
+            let frac: Vec<f64> = words[2..5]
+                .iter()
+                .map(|value| value.parse::<f64>().map_err(|_| bad_row()))
+                .collect::<Result<Vec<f64>, ParseCastepError>>()?;
+            let element = words[0].split(':').next().unwrap_or_default();
+            positions.push((element.to_string(), Point3::new(frac[0], frac[1], frac[2])));
+        }
+    }
+    Err(ParseCastepError("unterminated Cell Contents".to_string()))
+}
+
+/**
+Read the population table following the title at `lines[start]`, between the two `=====` rulers.
+Spin-polarized Mulliken tables of recent CASTEP versions print an `up:` row without charge
+followed by a `dn:` row with the charge and spin of the atom.
+# Arguments:
+- orbital_columns: `usize` - numeric columns before the charge (`s p d f Total` for Mulliken).
+# Returns:
+- (populations, index of the closing ruler)
+*/
+fn parse_population_table(
+    lines: &[&str],
+    start: usize,
+    orbital_columns: usize,
+) -> Result<(Vec<AtomPopulation>, usize), ParseCastepError> {
+    let rulers: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .skip(start)
+        .filter(|(_, line)| line.trim().starts_with("====="))
+        .map(|(idx, _)| idx)
+        .take(2)
+        .collect();
+    let (header, end) = match rulers[..] {
+        [first, second] => (lines[first - 1], second),
+        _ => {
+            return Err(ParseCastepError(format!(
+                "unterminated table '{}'",
+                lines[start].trim()
+            )))
+        }
+    };
+    let has_spin = header.contains("hbar");
+    let mut species = String::new();
+    let mut populations: Vec<AtomPopulation> = vec![];
+    for line in lines[rulers[0] + 1..end].iter() {
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if words[0].parse::<f64>().is_err() {
+            species = words.remove(0).to_string();
+        }
+        let bad_row = || ParseCastepError(format!("bad population row '{}'", line.trim()));
+        let ion = words
+            .first()
+            .and_then(|ion| ion.parse::<u32>().ok())
+            .ok_or_else(bad_row)?;
+        match words.get(1) {
+            Some(&"up:") => continue,
+            Some(&"dn:") => {
+                words.remove(1);
+            }
+            _ => {}
+        }
+        let values: Vec<f64> = words[1..]
+            .iter()
+            .map(|value| value.parse::<f64>().map_err(|_| bad_row()))
+            .collect::<Result<Vec<f64>, ParseCastepError>>()?;
+        let charge_columns = if has_spin { 2 } else { 1 };
+        if values.len() != orbital_columns + charge_columns {
+            return Err(bad_row());
+        }
+        let tail = &values[orbital_columns..];
+        populations.push(AtomPopulation {
+            species: species.clone(),
+            ion,
+            charge: tail[0],
+            spin: if has_spin { Some(tail[1]) } else { None },
+        });
+    }
+    Ok((populations, end))
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_castep() {
+    let text = " type of calculation                            : geometry optimization
+        Real Lattice(A)              Reciprocal Lattice(1/A)
+  10.0000000   0.0000000   0.0000000        0.628319   0.000000   0.000000
+   0.0000000  10.0000000   0.0000000        0.000000   0.628319   0.000000
+   0.0000000   0.0000000  20.0000000        0.000000   0.000000   0.314159
+ plane wave basis set cut-off                   :   500.0000   eV
+                    MP grid size for SCF calculation is  3  3  1
+ *Warning* max. SCF cycles performed but system has not reached the groundstate.
+Final energy, E             =  -3433.153417052     eV
+Final free energy (E-TS)    =  -3433.153592126     eV
+ BFGS: finished iteration     1 with enthalpy= -3.43315359E+003 eV
+Final energy, E             =  -3433.521108710     eV
+Final free energy (E-TS)    =  -3433.521250002     eV
+ BFGS: finished iteration     2 with enthalpy= -3.43352125E+003 eV
+ BFGS: Geometry optimization completed successfully.
+            xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx
+            x  Element    Atom        Fractional coordinates of atoms  x
+            x            Number           u          v          w      x
+            x---------------------------------------------------------x
+            x  C            1         0.000000   0.000000   0.500000   x
+            x  Fe:1         1         0.500000   0.000000   0.500000   x
+            xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx
+ BFGS: Final Enthalpy     = -3.43352125E+003 eV
+     Atomic Populations (Mulliken)
+     -----------------------------
+Species          Ion Spin      s       p       d       f      Total   Charge(e)   Spin(hbar/2)
+==============================================================================================
+  C              1   up:     0.541   1.461   0.000   0.000   2.002
+                 1   dn:     0.541   1.461   0.000   0.000   2.002   -0.004      0.000
+  Fe             1   up:     0.120   0.150   4.560   0.000   4.830
+                 1   dn:     0.110   0.140   2.320   0.000   2.570    0.600      2.260
+==============================================================================================
+     Hirshfeld Analysis
+     ------------------
+Species   Ion     Hirshfeld Charge (e)  Spin (hbar/2)
+======================================================
+  C        1                 -0.02          0.00
+  Fe       1                  0.31          2.18
+======================================================
+Total time          =    3600.12 s
+";
+    let output = parse_castep(text).unwrap();
+    assert_eq!(output.task(), Some("geometry optimization"));
+    let lattice = output.final_lattice("GDY_Fe").unwrap().unwrap();
+    assert_eq!(lattice.atoms_vec().len(), 2);
+    assert_eq!(lattice.atoms_vec()[1].element_name(), "Fe");
+    assert!((lattice.atoms_vec()[1].xyz().x - 5.0).abs() < 1e-8);
+    assert!((lattice.atoms_vec()[1].xyz().z - 10.0).abs() < 1e-8);
+    assert_eq!(output.final_energy(), Some(-3433.521108710));
+    assert_eq!(output.final_free_energy(), Some(-3433.521250002));
+    assert_eq!(output.final_enthalpy(), Some(-3433.52125));
+    assert_eq!(output.geom_steps(), 2);
+    assert_eq!(output.convergence(), Convergence::Converged);
+    // The geometry steps after the SCF warning recovered from it.
+    assert!(!output.scf_failed());
+    assert_eq!(output.warnings().len(), 1);
+    // A continuation appended to the finished run: its results only, while it is running.
+    let restarted = parse_castep(&format!(
+        "{}{}\n BFGS: finished iteration     1 with enthalpy= -3.43352200E+003 eV\n",
+        text,
+        text.lines().next().unwrap()
+    ))
+    .unwrap();
+    assert!(restarted.warnings().is_empty());
+    assert_eq!(restarted.convergence(), Convergence::Incomplete);
+    assert_eq!(restarted.geom_steps(), 1);
+    assert_eq!(
+        (
+            restarted.final_energy(),
+            restarted.final_enthalpy(),
+            restarted.energy()
+        ),
+        (None, None, None)
+    );
+    assert!(restarted.final_lattice("GDY_Fe").unwrap().is_none());
+    assert!(restarted.mulliken().is_empty() && restarted.cut_off_energy().is_none());
+    assert_eq!(output.cut_off_energy(), Some(500.0));
+    assert_eq!(output.kpoint_grid(), Some([3, 3, 1]));
+    assert_eq!(output.mulliken().len(), 2);
+    assert_eq!(output.mulliken()[1].species(), "Fe");
+    assert_eq!(output.mulliken()[1].charge(), 0.6);
+    assert_eq!(output.mulliken()[1].spin(), Some(2.26));
+    assert_eq!(output.hirshfeld()[1].charge(), 0.31);
+    assert_eq!(output.hirshfeld()[1].spin(), Some(2.18));
+
+    let single_point = " type of calculation                            : single point energy
+     Atomic Populations (Mulliken)
+     -----------------------------
+Species   Ion     s      p      d      f     Total  Charge (e)
+==============================================================
+  O        1     1.86   4.46   0.00   0.00   6.32    -0.32
+  C        1     1.49   2.19   0.00   0.00   3.68     0.32
+==============================================================
+Final energy =  -588.123456789     eV
+Total time          =      12.00 s
+";
+    let output = parse_castep(single_point).unwrap();
+    assert_eq!(output.convergence(), Convergence::Converged);
+    assert_eq!(output.energy(), Some(-588.123456789));
+    assert_eq!(output.mulliken()[0].charge(), -0.32);
+    assert_eq!(output.mulliken()[0].spin(), None);
+
+    let running = " type of calculation                            : geometry optimization
+ BFGS: finished iteration     7 with enthalpy= -3.43315359E+003 eV
+";
+    let output = parse_castep(running).unwrap();
+    assert_eq!(output.convergence(), Convergence::Incomplete);
+    assert_eq!(output.geom_steps(), 7);
+    assert_eq!(output.energy(), None);
+
+    let warnings = " type of calculation                            : geometry optimization
+ Warning: the k-point grid does not respect the symmetry of the cell.
+ WARNING: ignoring unknown keyword in the .param file.
+ Calculation not parallelised over warnings
+ Number of warnings is reported at the end
+";
+    let output = parse_castep(warnings).unwrap();
+    assert_eq!(output.warnings().len(), 2);
+    assert!(output.warnings()[1].starts_with("WARNING:"));
+}
@@ -0,0 +1,405 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use castep_model_generator_backend::parser::msi_parser::parse_lattice;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    adsorbate::{ads_model_name, ads_sites, AdsInfo, AdsSite, AdsTable},
+    castep_output::read_castep,
+    failure_report::{FailureReport, ModelFailure},
+    manifest::site_elements,
+    project_config::GdyProject,
+    restart::latest_attempt,
+};
+
+/**
+The `collect` entry of `project.yaml`: layout of the energy CSV read by `data_process::csv::EnergyCSV`.
+# Placeholders:
+- `row_name`: `{name}` - base model name; `{elements}` - substituted elements joined by `_`.
+- `column_name`: `{ads}` - adsorbate name; `{site}` - coordination site name.
+*/
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CollectSettings {
+    /// Header of the first column.
+    row_label: String,
+    row_name: String,
+    column_name: String,
+    /// Header of an extra column with the energy of the base model, left out when absent.
+    base_column: Option<String>,
+    /// Written for a model without `.castep` or without final energy.
+    /// `NaN` by default, so `data_process::csv::dataframe_to_vec_f64` still parses the row.
+    missing: String,
+    /// Written for a model whose geometry optimization has not converged; `NaN` by default.
+    unconverged: String,
+    /// CSV file, relative to `export_loc`.
+    output: String,
+    /// Companion CSV with the same layout and `converged`, `unconverged` or `missing` in each cell,
+    /// so the two kinds of `NaN` stay apart; left out when absent.
+    flags_output: Option<String>,
+}
+
+impl Default for CollectSettings {
+    fn default() -> Self {
+        Self {
+            row_label: "SAC_GDY_X".to_string(),
+            row_name: "{elements}".to_string(),
+            column_name: "{ads}_{site}".to_string(),
+            base_column: None,
+            missing: "NaN".to_string(),
+            unconverged: "NaN".to_string(),
+            output: "energies.csv".to_string(),
+            flags_output: Some("energy_flags.csv".to_string()),
+        }
+    }
+}
+
+impl CollectSettings {
+    pub fn row_label(&self) -> &str {
+        self.row_label.as_ref()
+    }
+
+    pub fn row_name(&self, name: &str, elements: &[String]) -> String {
+        self.row_name
+            .replace("{name}", name)
+            .replace("{elements}", &elements.join("_"))
+    }
+
+    pub fn column_name(&self, ads: &str, site: &str) -> String {
+        self.column_name
+            .replace("{ads}", ads)
+            .replace("{site}", site)
+    }
+
+    pub fn base_column(&self) -> Option<&str> {
+        self.base_column.as_deref()
+    }
+
+    pub fn missing(&self) -> &str {
+        self.missing.as_ref()
+    }
+
+    pub fn unconverged(&self) -> &str {
+        self.unconverged.as_ref()
+    }
+
+    pub fn output(&self) -> &str {
+        self.output.as_ref()
+    }
+
+    pub fn flags_output(&self) -> Option<&str> {
+        self.flags_output.as_deref()
+    }
+}
+
+/// Content of one cell of the energy CSV.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnergyCell {
+    /// Final enthalpy (or energy) of a converged calculation, in eV.
+    Energy(f64),
+    Unconverged,
+    Missing,
+}
+
+impl EnergyCell {
+    /// Cell of the flags CSV.
+    pub fn flag(&self) -> &'static str {
+        match self {
+            EnergyCell::Energy(_) => "converged",
+            EnergyCell::Unconverged => "unconverged",
+            EnergyCell::Missing => "missing",
+        }
+    }
+}
+
+/**
+Final energy of the calculation of `{seed_dir}/{name}.msi`, from `{name}.castep` of its latest attempt:
+the last `{seed_dir}_restart_N` directory, or `seed_dir` itself.
+# Returns:
+- `Missing` when the output does not exist or has no energy yet.
+*/
+pub fn energy_cell(msi_path: &Path) -> Result<EnergyCell, Box<dyn Error>> {
+    let seed_dir = msi_path.parent().unwrap_or(Path::new("."));
+    let castep_path = latest_attempt(seed_dir).join(
+        msi_path
+            .with_extension("castep")
+            .file_name()
+            .unwrap_or_default(),
+    );
+    if !castep_path.exists() {
+        return Ok(EnergyCell::Missing);
+    }
+    let output = read_castep(&castep_path)?;
+    Ok(match output.energy() {
+        None => EnergyCell::Missing,
+        Some(_) if !output.is_converged() => EnergyCell::Unconverged,
+        Some(energy) => EnergyCell::Energy(energy),
+    })
+}
+
+/// One row of the energy CSV: a base model with its adsorbate models.
+#[derive(Debug, Clone)]
+pub struct EnergyRow {
+    name: String,
+    base: EnergyCell,
+    cells: Vec<EnergyCell>,
+}
+
+impl EnergyRow {
+    pub fn new(name: String, base: EnergyCell, cells: Vec<EnergyCell>) -> Self {
+        Self { name, base, cells }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn base(&self) -> EnergyCell {
+        self.base
+    }
+
+    pub fn cells(&self) -> &[EnergyCell] {
+        self.cells.as_ref()
+    }
+}
+
+/// Energies of the base models (rows) and of their adsorbate models (columns).
+#[derive(Debug, Clone)]
+pub struct EnergyTable {
+    /// (adsorbate, site) of each column.
+    columns: Vec<(String, String)>,
+    rows: Vec<EnergyRow>,
+}
+
+impl EnergyTable {
+    pub fn new(columns: Vec<(String, String)>, rows: Vec<EnergyRow>) -> Self {
+        Self { columns, rows }
+    }
+
+    pub fn columns(&self) -> &[(String, String)] {
+        self.columns.as_ref()
+    }
+
+    pub fn rows(&self) -> &[EnergyRow] {
+        self.rows.as_ref()
+    }
+
+    /// Number of cells, base models included, matching `predicate`.
+    pub fn count<F: Fn(&EnergyCell) -> bool>(&self, predicate: F) -> usize {
+        self.rows
+            .iter()
+            .flat_map(|row| std::iter::once(&row.base).chain(row.cells.iter()))
+            .filter(|cell| predicate(cell))
+            .count()
+    }
+
+    pub fn csv_text(&self, settings: &CollectSettings) -> String {
+        self.table_text(settings, |cell| match cell {
+            EnergyCell::Energy(energy) => format!("{:.8}", energy),
+            EnergyCell::Unconverged => settings.unconverged().to_string(),
+            EnergyCell::Missing => settings.missing().to_string(),
+        })
+    }
+
+    /// Text of the flags CSV: the layout of `csv_text` with `EnergyCell::flag` in each cell.
+    pub fn flags_csv_text(&self, settings: &CollectSettings) -> String {
+        self.table_text(settings, |cell| cell.flag().to_string())
+    }
+
+    fn table_text<F: Fn(&EnergyCell) -> String>(
+        &self,
+        settings: &CollectSettings,
+        cell_text: F,
+    ) -> String {
+        let mut header: Vec<String> = vec![settings.row_label().to_string()];
+        if let Some(base_column) = settings.base_column() {
+            header.push(base_column.to_string());
+        }
+        self.columns
+            .iter()
+            .for_each(|(ads, site)| header.push(settings.column_name(ads, site)));
+        let mut lines: Vec<String> = vec![header.join(",")];
+        self.rows.iter().for_each(|row| {
+            let mut fields: Vec<String> = vec![row.name().to_string()];
+            if settings.base_column().is_some() {
+                fields.push(cell_text(&row.base));
+            }
+            row.cells
+                .iter()
+                .for_each(|cell| fields.push(cell_text(cell)));
+            lines.push(fields.join(","))
+        });
+        lines.join("\n") + "\n"
+    }
+}
+
+/**
+Gather the energies of the `base_models` and of their adsorbate models, at the locations
+written by `generate_all_ads_models`.
+# Returns:
+- (table, `.castep` or `.msi` files that could not be read); unreadable outputs are `Missing`.
+*/
+pub fn collect_energies(
+    base_models: &[PathBuf],
+    target_root_dir: &str,
+    ads_table: &AdsTable,
+    gdy_project: &GdyProject,
+    settings: &CollectSettings,
+) -> (EnergyTable, FailureReport) {
+    let naming = gdy_project.naming();
+    let placements: Vec<(&AdsInfo, AdsSite)> = ads_table
+        .adsorbates()
+        .iter()
+        .flat_map(|ads_info| {
+            ads_sites(ads_info, gdy_project)
+                .into_iter()
+                .map(move |site| (ads_info, site))
+        })
+        .collect();
+    let results: Vec<(EnergyRow, Vec<ModelFailure>)> = base_models
+        .par_iter()
+        .map(|msi_path| {
+            let mut failures: Vec<ModelFailure> = vec![];
+            let mut cell = |name: &str, path: &Path| {
+                energy_cell(path).unwrap_or_else(|e| {
+                    failures.push(ModelFailure::new(name, e));
+                    EnergyCell::Missing
+                })
+            };
+            let base_name = msi_path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let base = cell(&base_name, msi_path);
+            let cells: Vec<EnergyCell> = placements
+                .iter()
+                .map(|(ads_info, site)| {
+                    let name = ads_model_name(&base_name, ads_info, site);
+                    let path: PathBuf = [
+                        target_root_dir,
+                        ads_table.directory(),
+                        ads_info.path_name(),
+                        &base_name,
+                        &naming.seed_dir(&name),
+                        &format!("{}.msi", name),
+                    ]
+                    .iter()
+                    .collect();
+                    cell(&name, &path)
+                })
+                .collect();
+            let elements = match parse_lattice(&msi_path.to_string_lossy()) {
+                Ok(lattice) => site_elements(&lattice, gdy_project.substitution_sites()),
+                Err(e) => {
+                    failures.push(ModelFailure::new(&base_name, e));
+                    vec![]
+                }
+            };
+            (
+                EnergyRow::new(settings.row_name(&base_name, &elements), base, cells),
+                failures,
+            )
+        })
+        .collect();
+    let mut report = FailureReport::default();
+    let rows: Vec<EnergyRow> = results
+        .into_iter()
+        .map(|(row, failures)| {
+            failures
+                .into_iter()
+                .for_each(|failure| report.push(failure));
+            row
+        })
+        .collect();
+    let columns: Vec<(String, String)> = placements
+        .iter()
+        .map(|(ads_info, site)| (ads_info.name().to_string(), site.name().to_string()))
+        .collect();
+    (EnergyTable::new(columns, rows), report)
+}
+
+#[cfg(test)]
+#[test]
+fn test_energy_csv() {
+    let settings: CollectSettings =
+        serde_yaml::from_str("{base_column: GDY, missing: missing, unconverged: unconverged}")
+            .unwrap();
+    assert_eq!(
+        settings.row_name("GDY_Fe", &["Fe".to_string()]),
+        "Fe".to_string()
+    );
+    let table = EnergyTable::new(
+        vec![
+            ("CO2".to_string(), "c1".to_string()),
+            ("COOH".to_string(), "c1".to_string()),
+        ],
+        vec![
+            EnergyRow::new(
+                "Fe".to_string(),
+                EnergyCell::Energy(-3433.5),
+                vec![EnergyCell::Energy(-4460.25), EnergyCell::Unconverged],
+            ),
+            EnergyRow::new(
+                "Co".to_string(),
+                EnergyCell::Missing,
+                vec![EnergyCell::Missing, EnergyCell::Energy(-4473.125)],
+            ),
+        ],
+    );
+    assert_eq!(
+        table.csv_text(&settings),
+        "SAC_GDY_X,GDY,CO2_c1,COOH_c1
+Fe,-3433.50000000,-4460.25000000,unconverged
+Co,missing,missing,-4473.12500000
+"
+    );
+    assert_eq!(table.count(|cell| *cell == EnergyCell::Missing), 2);
+    assert_eq!(table.count(|cell| matches!(cell, EnergyCell::Energy(_))), 3);
+    // The default markers parse as f64 in data_process.
+    let text = table.csv_text(&CollectSettings::default());
+    assert_eq!(text.lines().nth(2), Some("Co,NaN,-4473.12500000"));
+    assert!(text
+        .lines()
+        .skip(1)
+        .flat_map(|line| line.split(',').skip(1))
+        .all(|field| field.parse::<f64>().is_ok()));
+    // Both are NaN there, the flags CSV tells them apart.
+    assert_eq!(text.lines().nth(1), Some("Fe,-4460.25000000,NaN"));
+    assert_eq!(
+        CollectSettings::default().flags_output(),
+        Some("energy_flags.csv")
+    );
+    assert_eq!(
+        table.flags_csv_text(&CollectSettings::default()),
+        "SAC_GDY_X,CO2_c1,COOH_c1
+Fe,converged,unconverged
+Co,missing,converged
+"
+    );
+
+    let dir = std::env::temp_dir().join("gdy_test_energy_cell");
+    let _ = std::fs::remove_dir_all(&dir);
+    let seed_dir = dir.join("GDY_Fe_opt");
+    let restart_dir = dir.join("GDY_Fe_opt_restart_1");
+    std::fs::create_dir_all(&seed_dir).unwrap();
+    std::fs::create_dir_all(&restart_dir).unwrap();
+    let msi = seed_dir.join("GDY_Fe.msi");
+    let header = " type of calculation                            : geometry optimization\n";
+    std::fs::write(seed_dir.join("GDY_Fe.castep"), header).unwrap();
+    assert_eq!(energy_cell(&msi).unwrap(), EnergyCell::Missing);
+    std::fs::write(
+        restart_dir.join("GDY_Fe.castep"),
+        format!(
+            "{}Final energy, E             =  -3433.521108710     eV\n BFGS: Geometry optimization completed successfully.\n BFGS: Final Enthalpy     = -3.43352125E+003 eV\n",
+            header
+        ),
+    )
+    .unwrap();
+    assert_eq!(energy_cell(&msi).unwrap(), EnergyCell::Energy(-3433.52125));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
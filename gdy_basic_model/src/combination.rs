@@ -0,0 +1,239 @@
+use castep_model_generator_backend::{atom::AtomArray, lattice::Lattice};
+use periodic_table::Element;
+use serde::Deserialize;
+
+use crate::{
+    editor::gdy_tri_editor::{change_atom_element, MissingAtomIdError},
+    project_config::SubstitutionSite,
+};
+
+/// Anything that can be put on a site and identified by its element symbol.
+pub trait Species: Copy {
+    fn symbol(&self) -> &str;
+    fn atomic_number(&self) -> u32;
+}
+
+impl Species for &Element {
+    fn symbol(&self) -> &str {
+        self.symbol
+    }
+    fn atomic_number(&self) -> u32 {
+        self.atomic_number
+    }
+}
+
+/// Sites sharing the same species, and the pool of species allowed on them.
+#[derive(Debug, Clone)]
+pub struct SiteGroup<T: Species> {
+    name: String,
+    atom_ids: Vec<u32>,
+    pool: Vec<T>,
+}
+
+impl<T: Species> SiteGroup<T> {
+    pub fn new(name: &str, atom_ids: Vec<u32>, pool: Vec<T>) -> Self {
+        Self {
+            name: name.to_string(),
+            atom_ids,
+            pool,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn atom_ids(&self) -> &[u32] {
+        self.atom_ids.as_ref()
+    }
+
+    pub fn pool(&self) -> &[T] {
+        self.pool.as_ref()
+    }
+}
+
+/// Group the substitution sites by their `group` label, all groups drawing from `pool`.
+pub fn groups_from_sites<T: Species>(sites: &[SubstitutionSite], pool: &[T]) -> Vec<SiteGroup<T>> {
+    let mut groups: Vec<SiteGroup<T>> = vec![];
+    sites.iter().for_each(|site| {
+        match groups.iter_mut().find(|group| group.name() == site.group()) {
+            Some(group) => group.atom_ids.push(site.atom_id()),
+            None => groups.push(SiteGroup::new(
+                site.group(),
+                vec![site.atom_id()],
+                pool.to_vec(),
+            )),
+        }
+    });
+    groups
+}
+
+/// Restrictions on the species assigned to the groups of one configuration.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Constraint {
+    /// Every group gets a different species.
+    AllDifferent,
+    /// At most `k` distinct species in a configuration.
+    AtMostDistinct { k: usize },
+    /// The two species of each pair never appear together.
+    ExcludePairs { pairs: Vec<(String, String)> },
+}
+
+impl Constraint {
+    pub fn is_satisfied<T: Species>(&self, configuration: &[T]) -> bool {
+        let mut symbols: Vec<&str> = configuration.iter().map(|s| s.symbol()).collect();
+        match self {
+            Constraint::AllDifferent => {
+                symbols.sort_unstable();
+                symbols.windows(2).all(|pair| pair[0] != pair[1])
+            }
+            Constraint::AtMostDistinct { k } => {
+                symbols.sort_unstable();
+                symbols.dedup();
+                symbols.len() <= *k
+            }
+            Constraint::ExcludePairs { pairs } => pairs.iter().all(|(a, b)| {
+                let has_a = symbols.contains(&a.as_str());
+                let has_b = symbols.contains(&b.as_str());
+                // A pair of identical symbols forbids using it on two groups.
+                if a == b {
+                    symbols.iter().filter(|&&s| s == a).count() < 2
+                } else {
+                    !(has_a && has_b)
+                }
+            }),
+        }
+    }
+}
+
+/**
+Lazy iterator over the valid configurations of a list of site groups.
+Each item holds one species per group, aligned with the groups.
+The first group varies slowest, as in nested loops.
+*/
+pub struct Configurations<'a, T: Species> {
+    groups: &'a [SiteGroup<T>],
+    constraints: &'a [Constraint],
+    indices: Vec<usize>,
+    exhausted: bool,
+}
+
+impl<'a, T: Species> Iterator for Configurations<'a, T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.exhausted {
+            let configuration: Vec<T> = self
+                .groups
+                .iter()
+                .zip(self.indices.iter())
+                .map(|(group, &i)| group.pool()[i])
+                .collect();
+            self.advance();
+            if self
+                .constraints
+                .iter()
+                .all(|constraint| constraint.is_satisfied(&configuration))
+            {
+                return Some(configuration);
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T: Species> Configurations<'a, T> {
+    /// Odometer step: increase the last index and carry to the front.
+    fn advance(&mut self) {
+        for pos in (0..self.indices.len()).rev() {
+            self.indices[pos] += 1;
+            if self.indices[pos] < self.groups[pos].pool().len() {
+                return;
+            }
+            self.indices[pos] = 0;
+        }
+        self.exhausted = true;
+    }
+}
+
+/**
+Enumerate lazily every configuration of `groups` satisfying all `constraints`.
+# Example:
+Single-atom catalysts: one group with one site; dual-atom: two groups with
+`AllDifferent`; high-entropy variants: N groups with `AtMostDistinct { k }`.
+*/
+pub fn enumerate_configurations<'a, T: Species>(
+    groups: &'a [SiteGroup<T>],
+    constraints: &'a [Constraint],
+) -> Configurations<'a, T> {
+    Configurations {
+        groups,
+        constraints,
+        indices: vec![0; groups.len()],
+        exhausted: groups.is_empty() || groups.iter().any(|group| group.pool().is_empty()),
+    }
+}
+
+/// Put the species of each group on all atoms of the group.
+pub fn apply_configuration<T: Species>(
+    lattice: &mut Lattice,
+    groups: &[SiteGroup<T>],
+    configuration: &[T],
+) -> Result<(), MissingAtomIdError> {
+    let lattice_name = lattice.lattice_name().to_string();
+    for (group, species) in groups.iter().zip(configuration.iter()) {
+        for &atom_id in group.atom_ids() {
+            let atom = lattice
+                .atoms_vec_mut()
+                .get_mut_atom_by_id(atom_id)
+                .ok_or_else(|| MissingAtomIdError::new(atom_id, &lattice_name))?;
+            change_atom_element(atom, species.symbol(), species.atomic_number());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+impl Species for &str {
+    fn symbol(&self) -> &str {
+        self
+    }
+    fn atomic_number(&self) -> u32 {
+        0
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_enumerate_configurations() {
+    let pool = vec!["Fe", "Co", "Ni"];
+    let groups = vec![
+        SiteGroup::new("A", vec![73, 74], pool.clone()),
+        SiteGroup::new("B", vec![75], pool.clone()),
+    ];
+    assert_eq!(enumerate_configurations(&groups, &[]).count(), 9);
+    let first: Vec<Vec<&str>> = enumerate_configurations(&groups, &[]).take(2).collect();
+    assert_eq!(first, vec![vec!["Fe", "Fe"], vec!["Fe", "Co"]]);
+    let all_different = [Constraint::AllDifferent];
+    assert_eq!(enumerate_configurations(&groups, &all_different).count(), 6);
+    let exclude = [Constraint::ExcludePairs {
+        pairs: vec![("Fe".to_string(), "Co".to_string())],
+    }];
+    assert_eq!(enumerate_configurations(&groups, &exclude).count(), 7);
+    let three_groups = vec![
+        SiteGroup::new("A", vec![73], pool.clone()),
+        SiteGroup::new("B", vec![74], pool.clone()),
+        SiteGroup::new("C", vec![75], pool),
+    ];
+    let at_most_two = [Constraint::AtMostDistinct { k: 2 }];
+    assert_eq!(
+        enumerate_configurations(&three_groups, &at_most_two).count(),
+        27 - 6
+    );
+    let constraints: Vec<Constraint> = serde_yaml::from_str(
+        "[{type: all_different}, {type: at_most_distinct, k: 2}, {type: exclude_pairs, pairs: [[Fe, Co]]}]",
+    )
+    .unwrap();
+    assert_eq!(constraints[1], Constraint::AtMostDistinct { k: 2 });
+}
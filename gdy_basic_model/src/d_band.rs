@@ -0,0 +1,706 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use castep_model_generator_backend::{atom::AtomArray, lattice::Lattice};
+use serde::Deserialize;
+
+use crate::{
+    collector::CollectSettings,
+    dos_seed::dos_seed_name,
+    geom_output::HARTREE_TO_EV,
+    job_script::{seed_dirs_in, seed_name_in},
+    manifest::site_elements,
+    project_config::GdyProject,
+    structure_import::read_lattice,
+};
+
+/// The `d_band` entry of `project.yaml`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DBandSettings {
+    /// Metal atoms, by `atom_id` of the generated models; the `substitution_sites` when empty.
+    atom_ids: Vec<u32>,
+    /// Energy window relative to the Fermi level in eV, e.g. `[-10.0, 5.0]`; all bands when absent.
+    window: Option<[f64; 2]>,
+    /// CSV file, relative to `export_loc`.
+    output: String,
+}
+
+impl Default for DBandSettings {
+    fn default() -> Self {
+        Self {
+            atom_ids: vec![],
+            window: None,
+            output: "d_band.csv".to_string(),
+        }
+    }
+}
+
+impl DBandSettings {
+    pub fn atom_ids(&self, gdy_project: &GdyProject) -> Vec<u32> {
+        if self.atom_ids.is_empty() {
+            gdy_project
+                .substitution_sites()
+                .iter()
+                .map(|site| site.atom_id())
+                .collect()
+        } else {
+            self.atom_ids.clone()
+        }
+    }
+
+    pub fn window(&self) -> Option<[f64; 2]> {
+        self.window
+    }
+
+    pub fn output(&self) -> &str {
+        self.output.as_ref()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsePdosError(pub String);
+
+impl Display for ParsePdosError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse PDOS output: {}", self.0)
+    }
+}
+
+impl Error for ParsePdosError {}
+
+/// Records of a Fortran unformatted sequential file, with the endianness of its first marker.
+struct FortranRecords<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> FortranRecords<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        let big_endian = bytes.len() >= 4 && bytes[0] == 0 && bytes[3] != 0;
+        Self {
+            bytes,
+            pos: 0,
+            big_endian,
+        }
+    }
+
+    fn marker(&self, at: usize) -> Result<usize, ParsePdosError> {
+        let bytes: [u8; 4] = self
+            .bytes
+            .get(at..at + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| ParsePdosError("unexpected end of file".to_string()))?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        } as usize)
+    }
+
+    fn next_record(&mut self) -> Result<&'a [u8], ParsePdosError> {
+        let len = self.marker(self.pos)?;
+        let start = self.pos + 4;
+        if self.marker(start + len)? != len {
+            return Err(ParsePdosError(format!(
+                "record markers do not match at byte {}",
+                self.pos
+            )));
+        }
+        self.pos = start + len + 4;
+        Ok(&self.bytes[start..start + len])
+    }
+
+    fn integers(&mut self) -> Result<Vec<i32>, ParsePdosError> {
+        let big_endian = self.big_endian;
+        Ok(self
+            .next_record()?
+            .chunks_exact(4)
+            .map(|chunk| {
+                let bytes: [u8; 4] = chunk.try_into().unwrap();
+                if big_endian {
+                    i32::from_be_bytes(bytes)
+                } else {
+                    i32::from_le_bytes(bytes)
+                }
+            })
+            .collect())
+    }
+
+    fn integer(&mut self) -> Result<i32, ParsePdosError> {
+        self.integers()?
+            .first()
+            .copied()
+            .ok_or_else(|| ParsePdosError("empty integer record".to_string()))
+    }
+
+    fn reals(&mut self) -> Result<Vec<f64>, ParsePdosError> {
+        let big_endian = self.big_endian;
+        Ok(self
+            .next_record()?
+            .chunks_exact(8)
+            .map(|chunk| {
+                let bytes: [u8; 8] = chunk.try_into().unwrap();
+                if big_endian {
+                    f64::from_be_bytes(bytes)
+                } else {
+                    f64::from_le_bytes(bytes)
+                }
+            })
+            .collect())
+    }
+}
+
+/// Orbital projections of `.pdos_weights`, written with `pdos_calculate_weights : true`.
+#[derive(Debug, Clone)]
+pub struct PdosWeights {
+    nspins: usize,
+    /// (species index, ion index within the species, angular momentum) of each orbital, 1-based.
+    orbitals: Vec<(u32, u32, u32)>,
+    /// Weights of each k-point by its index: `[spin][band][orbital]`.
+    kpoints: BTreeMap<u32, Vec<Vec<Vec<f64>>>>,
+}
+
+impl PdosWeights {
+    pub fn nspins(&self) -> usize {
+        self.nspins
+    }
+
+    pub fn orbitals(&self) -> &[(u32, u32, u32)] {
+        self.orbitals.as_ref()
+    }
+
+    pub fn kpoints(&self) -> &BTreeMap<u32, Vec<Vec<Vec<f64>>>> {
+        &self.kpoints
+    }
+}
+
+pub fn read_pdos_weights<P: AsRef<Path>>(path: P) -> Result<PdosWeights, Box<dyn Error>> {
+    Ok(parse_pdos_weights(&fs::read(path)?)?)
+}
+
+/**
+Parse a `.pdos_weights` file: the numbers of k-points, spins, orbitals and bands, the species,
+ion and angular momentum of each orbital, then for each k-point its index and coordinates and,
+for each spin, the spin index, the number of bands and one record of orbital weights per band.
+# Returns:
+- Error when a band record does not hold one weight per orbital.
+*/
+pub fn parse_pdos_weights(bytes: &[u8]) -> Result<PdosWeights, ParsePdosError> {
+    let mut records = FortranRecords::new(bytes);
+    let nkpts = records.integer()?;
+    let nspins = records.integer()? as usize;
+    let norbitals = records.integer()? as usize;
+    let _max_bands = records.integer()?;
+    let species = records.integers()?;
+    let ions = records.integers()?;
+    let ls = records.integers()?;
+    if species.len() != norbitals || ions.len() != norbitals || ls.len() != norbitals {
+        return Err(ParsePdosError(format!(
+            "expected {} orbitals in the header",
+            norbitals
+        )));
+    }
+    let orbitals = (0..norbitals)
+        .map(|i| (species[i] as u32, ions[i] as u32, ls[i] as u32))
+        .collect();
+    let mut kpoints = BTreeMap::new();
+    for _ in 0..nkpts {
+        let index = records.integers()?.first().copied().unwrap_or_default() as u32;
+        let mut spins: Vec<Vec<Vec<f64>>> = vec![];
+        for _ in 0..nspins {
+            let _spin = records.integer()?;
+            let nbands = records.integer()?;
+            let bands = (0..nbands)
+                .map(|band| {
+                    let weights = records.reals()?;
+                    if weights.len() != norbitals {
+                        return Err(ParsePdosError(format!(
+                            "band {} of k-point {} has {} weights for {} orbitals",
+                            band + 1,
+                            index,
+                            weights.len(),
+                            norbitals
+                        )));
+                    }
+                    Ok(weights)
+                })
+                .collect::<Result<Vec<Vec<f64>>, ParsePdosError>>()?;
+            spins.push(bands);
+        }
+        kpoints.insert(index, spins);
+    }
+    Ok(PdosWeights {
+        nspins,
+        orbitals,
+        kpoints,
+    })
+}
+
+/// Eigenvalues of a `.bands` file, in eV.
+#[derive(Debug, Clone)]
+pub struct Bands {
+    /// Fermi energy of each spin.
+    fermi: Vec<f64>,
+    /// (weight, eigenvalues `[spin][band]`) of each k-point by its index.
+    kpoints: BTreeMap<u32, (f64, Vec<Vec<f64>>)>,
+}
+
+impl Bands {
+    /// Fermi energy of `spin` (0-based); the only one without spin polarization.
+    pub fn fermi(&self, spin: usize) -> f64 {
+        self.fermi
+            .get(spin)
+            .or_else(|| self.fermi.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn kpoints(&self) -> &BTreeMap<u32, (f64, Vec<Vec<f64>>)> {
+        &self.kpoints
+    }
+}
+
+pub fn read_bands<P: AsRef<Path>>(path: P) -> Result<Bands, Box<dyn Error>> {
+    Ok(parse_bands(&fs::read_to_string(path)?)?)
+}
+
+/// Parse a `.bands` file, converting the Fermi energies and eigenvalues from Hartree to eV.
+pub fn parse_bands(text: &str) -> Result<Bands, ParsePdosError> {
+    let values = |line: &str| -> Vec<f64> {
+        line.split_whitespace()
+            .filter_map(|value| value.parse::<f64>().ok())
+            .collect()
+    };
+    let mut fermi: Vec<f64> = vec![];
+    let mut kpoints: BTreeMap<u32, (f64, Vec<Vec<f64>>)> = BTreeMap::new();
+    let mut current: Option<u32> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("Fermi energ") {
+            fermi = values(trimmed.split(')').next_back().unwrap_or_default())
+                .iter()
+                .map(|energy| energy * HARTREE_TO_EV)
+                .collect();
+        } else if trimmed.starts_with("K-point") {
+            let fields = values(trimmed);
+            match fields.as_slice() {
+                [index, _, _, _, weight] => {
+                    let index = *index as u32;
+                    kpoints.insert(index, (*weight, vec![]));
+                    current = Some(index);
+                }
+                _ => return Err(ParsePdosError(format!("bad k-point line '{}'", trimmed))),
+            }
+        } else if trimmed.starts_with("Spin component") {
+            let kpoint = current
+                .and_then(|index| kpoints.get_mut(&index))
+                .ok_or_else(|| ParsePdosError("spin component before the first k-point".into()))?;
+            kpoint.1.push(vec![]);
+        } else if let Some(spin) = current
+            .and_then(|index| kpoints.get_mut(&index))
+            .and_then(|kpoint| kpoint.1.last_mut())
+        {
+            if let Ok(energy) = trimmed.parse::<f64>() {
+                spin.push(energy * HARTREE_TO_EV);
+            }
+        }
+    }
+    if fermi.is_empty() {
+        return Err(ParsePdosError("no Fermi energy".to_string()));
+    }
+    Ok(Bands { fermi, kpoints })
+}
+
+/// Moments of the d-projected density of states of one atom and spin, in eV relative to the Fermi level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DBandMoments {
+    /// First moment, the d-band center.
+    center: f64,
+    /// Square root of the second central moment.
+    width: f64,
+    /// Fraction of the d states below the Fermi level.
+    filling: f64,
+    /// `center + 2 * width`, the upper edge of the band.
+    upper_edge: f64,
+}
+
+impl DBandMoments {
+    /**
+    Moments of the weighted energy levels `samples`, (energy relative to the Fermi level, weight).
+    # Returns:
+    - `None` when the weights sum to zero.
+    */
+    pub fn from_samples(samples: &[(f64, f64)]) -> Option<Self> {
+        let total: f64 = samples.iter().map(|(_, weight)| weight).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let center = samples
+            .iter()
+            .map(|(energy, weight)| energy * weight)
+            .sum::<f64>()
+            / total;
+        let width = (samples
+            .iter()
+            .map(|(energy, weight)| (energy - center).powi(2) * weight)
+            .sum::<f64>()
+            / total)
+            .sqrt();
+        let filling = samples
+            .iter()
+            .filter(|(energy, _)| *energy <= 0.0)
+            .map(|(_, weight)| weight)
+            .sum::<f64>()
+            / total;
+        Some(Self {
+            center,
+            width,
+            filling,
+            upper_edge: center + 2.0 * width,
+        })
+    }
+
+    pub fn center(&self) -> f64 {
+        self.center
+    }
+
+    pub fn width(&self) -> f64 {
+        self.width
+    }
+
+    pub fn filling(&self) -> f64 {
+        self.filling
+    }
+
+    pub fn upper_edge(&self) -> f64 {
+        self.upper_edge
+    }
+}
+
+/**
+d-band moments of the ion `ion` of the species `species` (CASTEP order, 1-based), one per spin.
+The levels are weighted by their k-point weight and the sum of the d orbital (`l = 2`) weights of the ion.
+# Arguments:
+- window: energy range relative to the Fermi level kept in the moments, all levels when `None`.
+*/
+pub fn d_band_moments(
+    pdos: &PdosWeights,
+    bands: &Bands,
+    species: u32,
+    ion: u32,
+    window: Option<[f64; 2]>,
+) -> Vec<Option<DBandMoments>> {
+    let d_orbitals: Vec<usize> = pdos
+        .orbitals
+        .iter()
+        .enumerate()
+        .filter(|(_, orbital)| **orbital == (species, ion, 2))
+        .map(|(i, _)| i)
+        .collect();
+    (0..pdos.nspins)
+        .map(|spin| {
+            let fermi = bands.fermi(spin);
+            let samples: Vec<(f64, f64)> = pdos
+                .kpoints
+                .iter()
+                .filter_map(|(index, weights)| {
+                    let (k_weight, eigenvalues) = bands.kpoints.get(index)?;
+                    Some(
+                        weights
+                            .get(spin)?
+                            .iter()
+                            .zip(eigenvalues.get(spin)?.iter())
+                            .map(|(band, energy)| {
+                                let d_weight: f64 =
+                                    d_orbitals.iter().filter_map(|i| band.get(*i)).sum();
+                                (energy - fermi, d_weight * k_weight)
+                            })
+                            .collect::<Vec<(f64, f64)>>(),
+                    )
+                })
+                .flatten()
+                .filter(|(energy, _)| {
+                    window
+                        .map(|[low, high]| *energy >= low && *energy <= high)
+                        .unwrap_or(true)
+                })
+                .collect();
+            DBandMoments::from_samples(&samples)
+        })
+        .collect()
+}
+
+/// Elements of `lattice` in the order of their first appearance, the CASTEP species order.
+fn castep_species(lattice: &Lattice) -> Vec<&str> {
+    let mut species: Vec<&str> = vec![];
+    lattice.atoms_vec().iter().for_each(|atom| {
+        if !species.contains(&atom.element_name()) {
+            species.push(atom.element_name())
+        }
+    });
+    species
+}
+
+/**
+CASTEP (species index, ion index) of the atom `atom_id` of `lattice`, 1-based. Species are
+numbered in the order of their first appearance and ions in file order within their species,
+as in the `.cell` written from the lattice.
+*/
+pub fn castep_ion(lattice: &Lattice, atom_id: u32) -> Option<(u32, u32)> {
+    let atoms = lattice.atoms_vec();
+    let position = atoms.iter().position(|atom| atom.atom_id() == atom_id)?;
+    let element = atoms[position].element_name();
+    let species = castep_species(lattice);
+    let species_index = species.iter().position(|name| *name == element)? as u32 + 1;
+    let ion = atoms[..position]
+        .iter()
+        .filter(|atom| atom.element_name() == element)
+        .count() as u32
+        + 1;
+    Some((species_index, ion))
+}
+
+/// d-band moments of one metal atom of one model and spin, a row of the d-band CSV.
+#[derive(Debug, Clone)]
+pub struct DBandRow {
+    /// Row name of the base model in the energy CSV.
+    row_name: String,
+    seed: String,
+    atom_id: u32,
+    element: String,
+    spin: usize,
+    moments: Option<DBandMoments>,
+}
+
+impl DBandRow {
+    pub fn row_name(&self) -> &str {
+        self.row_name.as_ref()
+    }
+
+    pub fn seed(&self) -> &str {
+        self.seed.as_ref()
+    }
+
+    pub fn atom_id(&self) -> u32 {
+        self.atom_id
+    }
+
+    pub fn element(&self) -> &str {
+        self.element.as_ref()
+    }
+
+    pub fn spin(&self) -> usize {
+        self.spin
+    }
+
+    pub fn moments(&self) -> Option<DBandMoments> {
+        self.moments
+    }
+}
+
+/**
+d-band rows of the `{seed}_DOS` seed in `dos_dir`. The atoms are located by `atom_id` in the
+`.msi` of the optimization, `{seed_dir}/{seed}.msi` next to `dos_dir`.
+The DOS `.cell` is written from the final geometry of the optimization, which CASTEP lists
+grouped by species: the atom order differs from the `.msi`, but the species order and the ion
+order within each species are kept, so `castep_ion` of the `.msi` holds for the DOS run.
+This is checked against the DOS `.cell` for every atom.
+*/
+pub fn dos_d_band_rows(
+    dos_dir: &Path,
+    gdy_project: &GdyProject,
+    collect_settings: &CollectSettings,
+    settings: &DBandSettings,
+) -> Result<Vec<DBandRow>, Box<dyn Error>> {
+    let dos_name = seed_name_in(dos_dir)?;
+    let seed = dos_name
+        .strip_suffix(&dos_seed_name(""))
+        .ok_or_else(|| ParsePdosError(format!("{} is not a DOS seed", dos_name)))?;
+    let msi_path = dos_dir
+        .with_file_name(gdy_project.naming().seed_dir(seed))
+        .join(format!("{}.msi", seed));
+    let lattice = read_lattice(&msi_path.to_string_lossy())?;
+    let elements = site_elements(&lattice, gdy_project.substitution_sites());
+    let row_name = collect_settings.row_name(seed, &elements);
+    let dos_lattice = read_lattice(&dos_dir.join(format!("{}.cell", dos_name)).to_string_lossy())?;
+    let dos_species = castep_species(&dos_lattice);
+    let pdos = read_pdos_weights(dos_dir.join(format!("{}.pdos_weights", dos_name)))?;
+    let bands = read_bands(dos_dir.join(format!("{}.bands", dos_name)))?;
+    let mut rows: Vec<DBandRow> = vec![];
+    for atom_id in settings.atom_ids(gdy_project) {
+        let atom = lattice
+            .atoms_vec()
+            .get_atom_by_id(atom_id)
+            .ok_or_else(|| ParsePdosError(format!("no atom {} in {}", atom_id, seed)))?;
+        let (species, ion) = castep_ion(&lattice, atom_id)
+            .ok_or_else(|| ParsePdosError(format!("no atom {} in {}", atom_id, seed)))?;
+        let dos_ions = dos_lattice
+            .atoms_vec()
+            .iter()
+            .filter(|dos_atom| dos_atom.element_name() == atom.element_name())
+            .count() as u32;
+        if dos_species.get(species as usize - 1) != Some(&atom.element_name()) || ion > dos_ions {
+            return Err(Box::new(ParsePdosError(format!(
+                "{} {} of {} is not species {} ion {} of {}.cell",
+                atom.element_name(),
+                atom_id,
+                seed,
+                species,
+                ion,
+                dos_name
+            ))));
+        }
+        d_band_moments(&pdos, &bands, species, ion, settings.window())
+            .into_iter()
+            .enumerate()
+            .for_each(|(spin, moments)| {
+                rows.push(DBandRow {
+                    row_name: row_name.clone(),
+                    seed: seed.to_string(),
+                    atom_id,
+                    element: atom.element_name().to_string(),
+                    spin: spin + 1,
+                    moments,
+                })
+            });
+    }
+    Ok(rows)
+}
+
+/// `_DOS` seed directories under `root_dir` with a `.pdos_weights` output.
+pub fn finished_dos_dirs(root_dir: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut dirs: Vec<PathBuf> = seed_dirs_in(root_dir, &dos_seed_name("*"))?
+        .into_iter()
+        .filter(|dir| {
+            fs::read_dir(dir)
+                .map(|entries| {
+                    entries.filter_map(|entry| entry.ok()).any(|entry| {
+                        entry.path().extension().and_then(|ext| ext.to_str())
+                            == Some("pdos_weights")
+                    })
+                })
+                .unwrap_or(false)
+        })
+        .collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+/**
+The rows as CSV: `{row_label},seed,atom_id,element,spin,d_center,d_width,d_filling,d_upper_edge`.
+The first column holds the row names of the energy CSV to join the two tables; an atom without
+d weight gets empty moments.
+*/
+pub fn d_band_csv_text(rows: &[DBandRow], collect_settings: &CollectSettings) -> String {
+    let mut lines = vec![format!(
+        "{},seed,atom_id,element,spin,d_center,d_width,d_filling,d_upper_edge",
+        collect_settings.row_label()
+    )];
+    rows.iter().for_each(|row| {
+        let moments = match row.moments {
+            Some(moments) => format!(
+                "{:.6},{:.6},{:.6},{:.6}",
+                moments.center, moments.width, moments.filling, moments.upper_edge
+            ),
+            None => ",,,".to_string(),
+        };
+        lines.push(format!(
+            "{},{},{},{},{},{}",
+            row.row_name, row.seed, row.atom_id, row.element, row.spin, moments
+        ))
+    });
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+#[test]
+fn test_d_band_moments() {
+    let mut bytes: Vec<u8> = vec![];
+    let mut record = |data: Vec<u8>| {
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(&data);
+        bytes.extend((data.len() as u32).to_le_bytes());
+    };
+    let ints = |values: &[i32]| values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let reals = |values: &[f64]| values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    // 1 k-point, 1 spin, orbitals: C s, Fe d, Fe d
+    record(ints(&[1]));
+    record(ints(&[1]));
+    record(ints(&[3]));
+    record(ints(&[2]));
+    record(ints(&[1, 2, 2]));
+    record(ints(&[1, 1, 1]));
+    record(ints(&[0, 2, 2]));
+    let mut kpoint: Vec<u8> = ints(&[1]);
+    kpoint.extend::<Vec<u8>>(reals(&[0.0, 0.0, 0.0]));
+    record(kpoint);
+    record(ints(&[1]));
+    record(ints(&[2]));
+    record(reals(&[0.5, 0.5, 0.25]));
+    record(reals(&[0.0, 0.25, 0.0]));
+    let pdos = parse_pdos_weights(&bytes).unwrap();
+    // A band record with a weight missing is rejected: replace the last one, 3 weights long.
+    let mut truncated = bytes[..bytes.len() - (4 + 3 * 8 + 4)].to_vec();
+    truncated.extend(16_u32.to_le_bytes());
+    truncated.extend(0.0_f64.to_le_bytes());
+    truncated.extend(0.25_f64.to_le_bytes());
+    truncated.extend(16_u32.to_le_bytes());
+    assert!(parse_pdos_weights(&truncated).is_err());
+    assert_eq!(pdos.nspins(), 1);
+    assert_eq!(pdos.orbitals()[1], (2, 1, 2));
+    let bands = parse_bands(&format!(
+        "Number of k-points   1
+Number of spin components 1
+Number of electrons    10.000
+Number of eigenvalues      2
+Fermi energy (in atomic units)     {:.8}
+Unit cell vectors
+   18.897261    0.000000    0.000000
+K-point    1  0.00000000  0.00000000  0.00000000  1.00000000
+Spin component    1
+  {:.8}
+  {:.8}
+",
+        0.0,
+        -2.0 / HARTREE_TO_EV,
+        2.0 / HARTREE_TO_EV
+    ))
+    .unwrap();
+    let moments = d_band_moments(&pdos, &bands, 2, 1, None)[0].unwrap();
+    // weights 0.75 at -2 eV and 0.25 at +2 eV
+    assert!((moments.center() - -1.0).abs() < 1e-6);
+    assert!((moments.width() - 3.0_f64.sqrt()).abs() < 1e-6);
+    assert!((moments.filling() - 0.75).abs() < 1e-9);
+    assert!((moments.upper_edge() - (-1.0 + 2.0 * 3.0_f64.sqrt())).abs() < 1e-6);
+    assert!(d_band_moments(&pdos, &bands, 1, 1, None)[0].is_none());
+    let windowed = d_band_moments(&pdos, &bands, 2, 1, Some([-5.0, 0.0]))[0].unwrap();
+    assert!((windowed.center() - -2.0).abs() < 1e-6);
+
+    // The DOS `.cell` lists the atoms grouped by species, as CASTEP does, yet the
+    // (species, ion) pairs of the `.msi` order still match.
+    let lattice = |name: &str, elements: [&str; 4]| {
+        crate::structure_import::build_lattice(
+            name,
+            nalgebra::Matrix3::identity() * 10.0,
+            elements
+                .iter()
+                .enumerate()
+                .map(|(i, elm)| (elm.to_string(), nalgebra::Point3::new(i as f64, 0.0, 0.0)))
+                .collect(),
+        )
+        .unwrap()
+    };
+    let msi = lattice("GDY_Fe_Co", ["C", "Fe", "C", "Co"]);
+    let dos = lattice("GDY_Fe_Co_DOS", ["C", "C", "Fe", "Co"]);
+    assert_eq!(castep_ion(&msi, 2), Some((2, 1)));
+    assert_eq!(castep_ion(&msi, 2), castep_ion(&dos, 3));
+    assert_eq!(castep_ion(&msi, 3), castep_ion(&dos, 2));
+    assert_eq!(castep_ion(&msi, 4), castep_ion(&dos, 4));
+}
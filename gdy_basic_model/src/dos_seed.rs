@@ -0,0 +1,157 @@
+use std::{
+    error::Error,
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    job_script::seed_name_in,
+    manifest::{backup_seed_files, planned_seed_status, WriteOptions, WriteStatus},
+    restart::last_geometry,
+    seed_export::{set_param_value, SeedExporter},
+};
+
+/// `{seed_name}_DOS`, the seed name and directory of the DOS stage.
+pub fn dos_seed_name(seed_name: &str) -> String {
+    format!("{}_DOS", seed_name)
+}
+
+/// Band structure k-points of the DOS stage, the Gamma point as in `KPOINTS_LIST`.
+fn bs_kpoints_block() -> String {
+    [
+        "%BLOCK BS_KPOINTS_LIST".to_string(),
+        format!("{:20.15}{:20.15}{:20.15}{:20.15}", 0.0, 0.0, 0.0, 1.0),
+        "%ENDBLOCK BS_KPOINTS_LIST\n".to_string(),
+    ]
+    .join("\n")
+}
+
+/**
+Write the `{seed}_DOS` seed of the converged optimization in `opt_dir`, as a sibling directory.
+The `.cell` holds the final geometry with the band structure k-points; the `.param` comes from
+the `dos.param` template of `dos_exporter` with `continuation` pointing to the `.check` of the
+optimization, `../{opt_dir}/{seed}.check`. Both are written directly, with the pseudopotentials,
+the extra structure formats and the job script of `dos_exporter`.
+The overwrite policy of `options` is applied to these files together.
+# Returns:
+- (DOS seed directory, status)
+*/
+pub fn write_dos_seed(
+    opt_dir: &Path,
+    dos_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Result<(PathBuf, WriteStatus), Box<dyn Error>> {
+    let seed_name = seed_name_in(opt_dir)?;
+    let dos_name = dos_seed_name(&seed_name);
+    let dos_dir = opt_dir.with_file_name(&dos_name);
+    let mut lattice = last_geometry(opt_dir, &seed_name)?;
+    lattice.set_lattice_name(dos_name.clone());
+    let cell_path = dos_dir.join(format!("{}.cell", dos_name));
+    let param_path = dos_dir.join(format!("{}.param", dos_name));
+    let check_pointer = Path::new("..")
+        .join(opt_dir.file_name().unwrap_or_default())
+        .join(format!("{}.check", seed_name));
+    let mut files = dos_exporter.seed_files(&lattice, &dos_dir)?;
+    for (path, text) in files.iter_mut() {
+        if *path == cell_path {
+            text.push('\n');
+            text.push_str(&bs_kpoints_block());
+        } else if *path == param_path {
+            *text = set_param_value(text, "continuation", &check_pointer.to_string_lossy());
+        }
+    }
+    let status = planned_seed_status(&files, options.policy())?;
+    if !status.is_written() || options.dry_run() {
+        return Ok((dos_dir, status));
+    }
+    if status == WriteStatus::BackedUp {
+        backup_seed_files(&dos_dir, &dos_name, &[])?;
+    }
+    create_dir_all(&dos_dir)?;
+    dos_exporter.write_seed_files(&lattice, &dos_dir, &files)?;
+    Ok((dos_dir, status))
+}
+
+#[cfg(test)]
+#[test]
+fn test_dos_param() {
+    use std::fs;
+
+    let template = fs::read_to_string("./resources/dos.param").unwrap();
+    let text = set_param_value(
+        &template,
+        "continuation",
+        "../GDY_Fe_Fe_Co_opt/GDY_Fe_Fe_Co.check",
+    );
+    assert!(text.contains("continuation : ../GDY_Fe_Fe_Co_opt/GDY_Fe_Fe_Co.check\n"));
+    assert!(text.starts_with("task : BandStructure\n"));
+    assert_eq!(dos_seed_name("GDY_Fe_Fe_Co"), "GDY_Fe_Fe_Co_DOS");
+    assert!(bs_kpoints_block().starts_with("%BLOCK BS_KPOINTS_LIST\n"));
+
+    let dir = std::env::temp_dir().join("gdy_test_dos_seed");
+    let _ = fs::remove_dir_all(&dir);
+    let opt_dir = dir.join("GDY_C_opt");
+    create_dir_all(&opt_dir).unwrap();
+    fs::write(opt_dir.join("GDY_C.param"), "task : GeometryOptimization\n").unwrap();
+    fs::write(
+        opt_dir.join("GDY_C.castep"),
+        " type of calculation                            : geometry optimization
+        Real Lattice(A)              Reciprocal Lattice(1/A)
+  10.0000000   0.0000000   0.0000000        0.628319   0.000000   0.000000
+   0.0000000  10.0000000   0.0000000        0.000000   0.628319   0.000000
+   0.0000000   0.0000000  20.0000000        0.000000   0.000000   0.314159
+            xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx
+            x  Element    Atom        Fractional coordinates of atoms  x
+            x            Number           u          v          w      x
+            x---------------------------------------------------------x
+            x  C            1         0.000000   0.000000   0.500000   x
+            xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx
+ BFGS: Geometry optimization completed successfully.
+",
+    )
+    .unwrap();
+    let dos_exporter = SeedExporter::new(
+        "./resources/element_table.yaml",
+        "./resources/dos.param",
+        None,
+        crate::potentials::PotentialResolver::new(
+            "./resources/potentials",
+            crate::potentials::PotentialBundling::None,
+        ),
+        crate::structure_export::StructureFormats::default(),
+    )
+    .unwrap();
+    let options = WriteOptions::new(false, crate::manifest::OverwritePolicy::Skip);
+    let (dos_dir, status) = write_dos_seed(&opt_dir, &dos_exporter, &options).unwrap();
+    assert_eq!(
+        (dos_dir.clone(), status),
+        (dir.join("GDY_C_DOS"), WriteStatus::Created)
+    );
+    let param = fs::read_to_string(dos_dir.join("GDY_C_DOS.param")).unwrap();
+    assert!(param.contains("continuation : ../GDY_C_opt/GDY_C.check\n"));
+    assert!(param.starts_with("task : BandStructure\n"));
+    let cell = fs::read_to_string(dos_dir.join("GDY_C_DOS.cell")).unwrap();
+    let bs_kpoints: Vec<&str> = cell
+        .split("%BLOCK BS_KPOINTS_LIST\n")
+        .nth(1)
+        .and_then(|rest| rest.split("%ENDBLOCK BS_KPOINTS_LIST").next())
+        .unwrap()
+        .lines()
+        .collect();
+    assert_eq!(
+        bs_kpoints
+            .iter()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+            .collect::<Vec<_>>(),
+        [[
+            "0.000000000000000",
+            "0.000000000000000",
+            "0.000000000000000",
+            "1.000000000000000"
+        ]]
+    );
+    assert!(cell.contains("%BLOCK POSITIONS_FRAC\n"));
+    let (_, status) = write_dos_seed(&opt_dir, &dos_exporter, &options).unwrap();
+    assert_eq!(status, WriteStatus::Unchanged);
+    fs::remove_dir_all(&dir).unwrap();
+}
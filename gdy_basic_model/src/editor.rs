@@ -1,6 +1,8 @@
 pub mod gdy_tri_editor {
     use std::{
+        collections::{hash_map::Entry, HashMap},
         error::Error,
+        fmt::Display,
         fs::{self, create_dir_all},
         path::{Path, PathBuf},
     };
@@ -10,10 +12,36 @@ pub mod gdy_tri_editor {
     use indicatif::ProgressBar;
     use periodic_table as pt;
     use pt::Element;
+    use rayon::prelude::*;
 
-    use castep_model_generator_backend::parser::msi_parser::parse_lattice;
-    fn lattice_update_base_name(target_lattice: &mut Lattice) {
-        todo!();
+    use crate::combination::{
+        apply_configuration, enumerate_configurations, groups_from_sites, SiteGroup,
+    };
+    use crate::element_selection::family_dir;
+    use crate::failure_report::{FailureReport, ModelFailure};
+    use crate::manifest::{
+        site_elements, summary, validation_report, write_manifest, write_validated, ManifestEntry,
+        WriteOptions,
+    };
+    use crate::project_config::{
+        site_groups, GdyProject, NamingScheme, SubstitutionSite, UnknownSiteGroupError,
+    };
+    use crate::seed_export::{species_list, SeedExporter};
+    use crate::symmetry::SiteSymmetry;
+    use crate::validation::model_issues;
+
+    use crate::structure_import::read_lattice;
+    /// Rename the lattice after the metals assigned to the site groups.
+    pub fn lattice_update_base_name(
+        target_lattice: &mut Lattice,
+        naming: &NamingScheme,
+        sites: &[SubstitutionSite],
+        groups: &[&str],
+        group_metals: &[&Element],
+    ) -> Result<(), UnknownSiteGroupError> {
+        let symbols: Vec<&str> = group_metals.iter().map(|metal| metal.symbol).collect();
+        target_lattice.set_lattice_name(naming.model_name(sites, groups, &symbols)?);
+        Ok(())
     }
     pub fn change_atom_element(
         target_atom: &mut Atom,
@@ -23,82 +51,389 @@ pub mod gdy_tri_editor {
         target_atom.set_element_name(new_element_name);
         target_atom.set_element_id(new_element_id);
     }
+    /// Atom ID required by a substitution site but absent in the lattice.
+    #[derive(Debug, Clone)]
+    pub struct MissingAtomIdError {
+        atom_id: u32,
+        lattice_name: String,
+    }
+
+    impl Display for MissingAtomIdError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "Atom with id {} not found in lattice {}",
+                self.atom_id, self.lattice_name
+            )
+        }
+    }
+
+    impl MissingAtomIdError {
+        pub fn new(atom_id: u32, lattice_name: &str) -> Self {
+            Self {
+                atom_id,
+                lattice_name: lattice_name.to_string(),
+            }
+        }
+    }
+
+    impl Error for MissingAtomIdError {}
+
+    /// Ensure every substitution site exists in the lattice before any file is written.
+    pub fn check_substitution_sites(
+        target_lattice: &Lattice,
+        sites: &[SubstitutionSite],
+    ) -> Result<(), MissingAtomIdError> {
+        match sites.iter().find(|site| {
+            target_lattice
+                .atoms_vec()
+                .get_atom_by_id(site.atom_id())
+                .is_none()
+        }) {
+            Some(site) => Err(MissingAtomIdError::new(
+                site.atom_id(),
+                target_lattice.lattice_name(),
+            )),
+            None => Ok(()),
+        }
+    }
+    /**
+    Generate models for every assignment of metals to the site groups,
+    e.g. 44^2 models for an "AAB" pattern, 44^3 for "ABC", subject to `constraints`.
+    Models are grouped under the directory of the metal of the first group.
+    The combinations are drawn lazily in chunks of `CHUNK_SIZE`, each chunk processed in parallel,
+    every model on its own clone of `target_lattice`.
+    When deduplication is enabled, assignments related by a symmetry operation of `target_lattice`
+    are skipped and recorded in `{target_root_dir}/symmetry_equivalents.csv`.
+    A model that cannot be generated is reported and the run goes on with the others.
+    # Returns:
+    - The models left out of the run; errors affecting every model are returned as `Err`.
+    */
     pub fn iterate_over_elements(
-        target_lattice: &mut Lattice,
+        target_lattice: &Lattice,
         target_root_dir: &str,
-        to_use_metals: &Vec<&Element>,
-    ) -> Result<(), Box<dyn Error>> {
-        let mut export_dirs: Vec<PathBuf> = vec![];
-        for metal in to_use_metals.iter() {
-            export_dirs.push(export_destination(metal, target_root_dir)?);
+        to_use_metals: &[&Element],
+        gdy_project: &GdyProject,
+        seed_exporter: &SeedExporter,
+        options: &WriteOptions,
+    ) -> Result<FailureReport, Box<dyn Error>> {
+        let sites = gdy_project.substitution_sites();
+        let naming = gdy_project.naming();
+        let symmetry_settings = gdy_project.symmetry();
+        check_substitution_sites(target_lattice, sites)?;
+        let groups = site_groups(sites);
+        let site_group_list = groups_from_sites(sites, to_use_metals);
+        let mut deduplicator = if symmetry_settings.deduplicate() {
+            let symmetry = SiteSymmetry::detect(target_lattice, sites, symmetry_settings)?;
+            Some(SymmetryDeduplicator::new(sites, &groups, naming, symmetry)?)
+        } else {
+            None
+        };
+        let mut combinations =
+            enumerate_configurations(&site_group_list, gdy_project.constraints());
+        let mut total: usize = 0;
+        let bar = ProgressBar::new_spinner();
+        let mut results: Vec<Result<ManifestEntry, ModelFailure>> = vec![];
+        loop {
+            let chunk: Vec<Vec<&Element>> = combinations
+                .by_ref()
+                .inspect(|_| total += 1)
+                .filter(|group_metals| {
+                    deduplicator
+                        .as_mut()
+                        .is_none_or(|deduplicator| deduplicator.keep(group_metals))
+                })
+                .take(CHUNK_SIZE)
+                .collect();
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk_results: Vec<Result<ManifestEntry, ModelFailure>> = chunk
+                .par_iter()
+                .map(|group_metals| {
+                    let mut lattice = target_lattice.clone();
+                    let result = write_model(
+                        &mut lattice,
+                        target_root_dir,
+                        &site_group_list,
+                        group_metals,
+                        gdy_project,
+                        seed_exporter,
+                        options,
+                    )
+                    .map_err(|e| {
+                        let symbols: Vec<&str> =
+                            group_metals.iter().map(|metal| metal.symbol).collect();
+                        let name = naming
+                            .model_name(sites, &groups, &symbols)
+                            .unwrap_or_else(|_| symbols.join("_"));
+                        ModelFailure::new(&name, e)
+                    });
+                    bar.inc(1);
+                    result
+                })
+                .collect();
+            results.extend(chunk_results);
+            bar.set_message(format!("{} models", results.len()));
         }
-        let metals_dirs = to_use_metals.iter().zip(export_dirs);
-        let bar = ProgressBar::new((to_use_metals.len().pow(2)) as u64);
-        for (item, dir) in metals_dirs {
-            for item_b in to_use_metals.iter() {
-                let atom_1 = target_lattice
-                    .atoms_vec_mut()
-                    .get_mut_atom_by_id(73)
-                    .unwrap();
-                change_atom_element(atom_1, item.symbol, item.atomic_number);
-                let atom_2 = target_lattice
-                    .atoms_vec_mut()
-                    .get_mut_atom_by_id(74)
-                    .unwrap();
-                change_atom_element(atom_2, item.symbol, item.atomic_number);
-                let atom_3 = target_lattice
-                    .atoms_vec_mut()
-                    .get_mut_atom_by_id(75)
-                    .unwrap();
-                change_atom_element(atom_3, item_b.symbol, item_b.atomic_number);
-                let text = target_lattice.format_output();
-                let lat_name = target_lattice.lattice_name();
-                let filepath = dir.join(format!("{}_opt/{}.msi", &lat_name, &lat_name));
-                if !filepath.exists() {
-                    let parent = filepath.parent().unwrap();
-                    if !parent.exists() {
-                        create_dir_all(&parent).unwrap_or_else(|why| {
-                            println!("! {:?}", why.kind());
-                        })
-                    }
-                    fs::write(filepath, text).expect("unable to write file");
+        bar.finish();
+        if let Some(deduplicator) = deduplicator {
+            if !options.dry_run() {
+                write_equivalents(target_root_dir, deduplicator.equivalents())?;
+            }
+            println!(
+                "{} of {} combinations are symmetry-unique",
+                results.len(),
+                total
+            );
+        }
+        let (entries, report) = FailureReport::collect(results);
+        validation_report(&entries)
+            .iter()
+            .for_each(|line| println!("{}", line));
+        if options.dry_run() {
+            println!("{}", summary(&entries, options));
+            return Ok(report);
+        }
+        write_manifest(Path::new(target_root_dir), "manifest", &entries)?;
+        println!("{}", summary(&entries, options));
+        Ok(report)
+    }
+    /// Number of combinations drawn from the iterator and generated in parallel at a time.
+    const CHUNK_SIZE: usize = 256;
+    /**
+    Keep the first combination of each symmetry class, in iteration order.
+    Combinations only merge when the `site_groups` pattern generates permuted assignments,
+    e.g. one group per site; with groups `A A B` every combination is kept.
+    Only the key of each class is held, so it can filter the combinations as they are drawn.
+    */
+    struct SymmetryDeduplicator<'a> {
+        sites: &'a [SubstitutionSite],
+        groups: &'a [&'a str],
+        naming: &'a NamingScheme,
+        symmetry: SiteSymmetry,
+        /// Index in `groups` of the group of each site.
+        site_group_index: Vec<usize>,
+        /// Canonical key of each class -> name of the model computed for it.
+        representatives: HashMap<Vec<String>, String>,
+        /// (skipped model name, equivalent computed model name)
+        equivalents: Vec<(String, String)>,
+    }
+
+    impl<'a> SymmetryDeduplicator<'a> {
+        fn new(
+            sites: &'a [SubstitutionSite],
+            groups: &'a [&'a str],
+            naming: &'a NamingScheme,
+            symmetry: SiteSymmetry,
+        ) -> Result<Self, Box<dyn Error>> {
+            let site_group_index: Vec<usize> = sites
+                .iter()
+                .map(|site| {
+                    groups
+                        .iter()
+                        .position(|&g| g == site.group())
+                        .ok_or_else(|| UnknownSiteGroupError::new(site))
+                })
+                .collect::<Result<Vec<usize>, UnknownSiteGroupError>>()?;
+            Ok(Self {
+                sites,
+                groups,
+                naming,
+                symmetry,
+                site_group_index,
+                representatives: HashMap::new(),
+                equivalents: vec![],
+            })
+        }
+        /// `true` for the first combination of its class; the others are recorded as equivalents.
+        fn keep(&mut self, group_metals: &[&Element]) -> bool {
+            let group_symbols: Vec<&str> = group_metals.iter().map(|metal| metal.symbol).collect();
+            let site_symbols: Vec<&str> = self
+                .site_group_index
+                .iter()
+                .map(|&idx| group_symbols[idx])
+                .collect();
+            // Every site group is known since `new`.
+            let name = self
+                .naming
+                .model_name(self.sites, self.groups, &group_symbols)
+                .unwrap_or_else(|_| group_symbols.join("_"));
+            match self
+                .representatives
+                .entry(self.symmetry.canonical_key(&site_symbols))
+            {
+                Entry::Occupied(entry) => {
+                    self.equivalents.push((name, entry.get().to_string()));
+                    false
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(name);
+                    true
                 }
-                bar.inc(1)
             }
         }
-        Ok(bar.finish())
+        fn equivalents(&self) -> &[(String, String)] {
+            &self.equivalents
+        }
     }
-    pub fn export_destination(
-        element: &Element,
+    fn write_equivalents(
         target_root_dir: &str,
-    ) -> Result<PathBuf, Box<dyn Error>> {
-        let family: &str = match element.atomic_number {
-            21..=30 => "3d",
-            39..=48 => "4d",
-            72..=80 => "5d",
-            57..=71 => "rare_earth",
-            _ => "else",
-        };
-        let dir_path = format!("./{}/{}/{}", target_root_dir, family, element.symbol);
-        create_dir_all(&dir_path)?;
-        Ok(Path::new(&dir_path).to_path_buf())
+        equivalents: &[(String, String)],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut lines = vec!["skipped,equivalent_to".to_string()];
+        equivalents
+            .iter()
+            .for_each(|(skipped, computed)| lines.push(format!("{},{}", skipped, computed)));
+        let dir = Path::new(target_root_dir);
+        create_dir_all(dir)?;
+        fs::write(dir.join("symmetry_equivalents.csv"), lines.join("\n"))?;
+        Ok(())
     }
+    /**
+    Substitute, rename and write one model with its seed files.
+    Existing files are handled by the overwrite policy of `options`;
+    the manifest entry tells what has been done.
+    */
+    fn write_model(
+        lattice: &mut Lattice,
+        target_root_dir: &str,
+        site_group_list: &[SiteGroup<&Element>],
+        group_metals: &[&Element],
+        gdy_project: &GdyProject,
+        seed_exporter: &SeedExporter,
+        options: &WriteOptions,
+    ) -> Result<ManifestEntry, Box<dyn Error>> {
+        let sites = gdy_project.substitution_sites();
+        let naming = gdy_project.naming();
+        apply_configuration(lattice, site_group_list, group_metals)?;
+        let groups: Vec<&str> = site_group_list.iter().map(|group| group.name()).collect();
+        lattice_update_base_name(lattice, naming, sites, &groups, group_metals)?;
+        let dir = export_destination(group_metals[0], target_root_dir);
+        let text = lattice.format_output();
+        let lat_name = lattice.lattice_name();
+        let filepath = dir
+            .join(naming.seed_dir(lat_name))
+            .join(format!("{}.msi", lat_name));
+        let seed_dir = filepath.parent().unwrap_or(Path::new("."));
+        let seed_files = seed_exporter.seed_files(lattice, seed_dir)?;
+        let validation = gdy_project.validation();
+        let issues = model_issues(lattice, validation)?;
+        let (status, hash) = write_validated(
+            &filepath,
+            &text,
+            &seed_files,
+            &issues,
+            validation.skip_invalid(),
+            options,
+        )?;
+        if status.is_written() && !options.dry_run() {
+            seed_exporter.write_seed_files(lattice, seed_dir, &seed_files)?;
+        }
+        Ok(ManifestEntry::new(
+            lat_name,
+            site_elements(lattice, sites),
+            &filepath,
+            hash,
+            status,
+            issues,
+        ))
+    }
+    /// `{target_root_dir}/{family}/{element}`; created when the first model is written.
+    pub fn export_destination(element: &Element, target_root_dir: &str) -> PathBuf {
+        let dir_path = format!(
+            "./{}/{}/{}",
+            target_root_dir,
+            family_dir(element),
+            element.symbol
+        );
+        Path::new(&dir_path).to_path_buf()
+    }
+    /// Generate the models of `metals`, e.g. from `ElementSelection::select`.
     pub fn generate_all_base_models(
         src_filename: &str,
         target_root_dir: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        let mut src_lattice = parse_lattice(src_filename)?;
-        let elements: &[&Element] = pt::periodic_table();
-        let metals_3d: &[&Element] = &elements[20..30];
-        let metals_4d: &[&Element] = &elements[38..48];
-        let metals_5d: &[&Element] = &elements[71..80];
-        let metals_rare_earth: &[&Element] = &elements[56..71];
-        let mut total_elements: Vec<&Element> = vec![];
-        total_elements.extend_from_slice(metals_3d);
-        total_elements.extend_from_slice(metals_4d);
-        total_elements.extend_from_slice(metals_5d);
-        total_elements.extend_from_slice(metals_rare_earth);
-        iterate_over_elements(&mut src_lattice, target_root_dir, &total_elements)?;
-        Ok(())
+        metals: &[&Element],
+        gdy_project: &GdyProject,
+        seed_exporter: &SeedExporter,
+        options: &WriteOptions,
+    ) -> Result<FailureReport, Box<dyn Error>> {
+        let src_lattice = read_lattice(src_filename)?;
+        let mut symbols: Vec<&str> = species_list(&src_lattice);
+        metals.iter().for_each(|elm| {
+            if !symbols.contains(&elm.symbol) {
+                symbols.push(elm.symbol)
+            }
+        });
+        seed_exporter.check_potentials(&symbols)?;
+        iterate_over_elements(
+            &src_lattice,
+            target_root_dir,
+            metals,
+            gdy_project,
+            seed_exporter,
+            options,
+        )
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn test_deduplicate_combinations() {
+        use crate::element_selection::element_by_symbol;
+        use crate::symmetry::SymmetrySettings;
+
+        let lattice = read_lattice("./resources/GDY_tri.msi").unwrap();
+        let metals = |symbols: &[&str]| -> Vec<&'static Element> {
+            symbols
+                .iter()
+                .map(|symbol| element_by_symbol(symbol).unwrap())
+                .collect()
+        };
+        let naming = NamingScheme::default();
+        // One group per site: the six arrangements of Fe, Co and Ni are related by the
+        // rotations and reflections of the metal triangle.
+        let abc_sites: Vec<SubstitutionSite> = serde_yaml::from_str(
+            "[{name: M1, atom_id: 73, group: A}, {name: M2, atom_id: 74, group: B}, {name: M3, atom_id: 75, group: C}]",
+        )
+        .unwrap();
+        let symmetry =
+            SiteSymmetry::detect(&lattice, &abc_sites, &SymmetrySettings::default()).unwrap();
+        let combinations: Vec<Vec<&Element>> = [
+            ["Fe", "Co", "Ni"],
+            ["Fe", "Ni", "Co"],
+            ["Co", "Fe", "Ni"],
+            ["Co", "Ni", "Fe"],
+            ["Ni", "Fe", "Co"],
+            ["Ni", "Co", "Fe"],
+        ]
+        .iter()
+        .map(|symbols| metals(symbols))
+        .collect();
+        let groups = site_groups(&abc_sites);
+        let mut deduplicator =
+            SymmetryDeduplicator::new(&abc_sites, &groups, &naming, symmetry).unwrap();
+        let unique: Vec<&Vec<&Element>> = combinations
+            .iter()
+            .filter(|group_metals| deduplicator.keep(group_metals))
+            .collect();
+        assert_eq!(unique.len(), 1);
+        assert_eq!(deduplicator.equivalents().len(), 5);
+        let first: Vec<&str> = unique[0].iter().map(|metal| metal.symbol).collect();
+        assert_eq!(first, ["Fe", "Co", "Ni"]);
+        // The shipped `A A B` pattern never generates a permuted assignment.
+        let aab_sites: Vec<SubstitutionSite> = serde_yaml::from_str(
+            "[{name: M1, atom_id: 73, group: A}, {name: M2, atom_id: 74, group: A}, {name: M3, atom_id: 75, group: B}]",
+        )
+        .unwrap();
+        let symmetry =
+            SiteSymmetry::detect(&lattice, &aab_sites, &SymmetrySettings::default()).unwrap();
+        let combinations = [metals(&["Fe", "Co"]), metals(&["Co", "Fe"])];
+        let groups = site_groups(&aab_sites);
+        let mut deduplicator =
+            SymmetryDeduplicator::new(&aab_sites, &groups, &naming, symmetry).unwrap();
+        assert!(combinations
+            .iter()
+            .all(|group_metals| deduplicator.keep(group_metals)));
+        assert!(deduplicator.equivalents().is_empty());
     }
 }
@@ -0,0 +1,321 @@
+use std::{error::Error, fmt::Display, ops::RangeInclusive, str::FromStr};
+
+/**
+Select the metals to substitute by family, period, group or symbol,
+from the `elements` entry of `project.yaml`.
+The families also decide the export directory of each element.
+*/
+use castep_model_generator_backend::{atom::AtomArray, lattice::Lattice};
+use periodic_table as pt;
+use pt::Element;
+use serde::Deserialize;
+
+use crate::{editor::gdy_tri_editor::MissingAtomIdError, project_config::SubstitutionSite};
+
+#[derive(Debug, Clone)]
+pub struct UnknownSymbolError(pub String);
+
+impl Display for UnknownSymbolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not an element symbol", self.0)
+    }
+}
+
+impl Error for UnknownSymbolError {}
+
+/// Metal families of the generated models, each exported into its own directory.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    #[serde(rename = "3d")]
+    D3,
+    #[serde(rename = "4d")]
+    D4,
+    #[serde(rename = "5d")]
+    D5,
+    #[serde(rename = "rare_earth")]
+    RareEarth,
+}
+
+impl Family {
+    pub const ALL: [Family; 4] = [Family::D3, Family::D4, Family::D5, Family::RareEarth];
+
+    /// Atomic numbers of the family members.
+    pub fn atomic_numbers(&self) -> RangeInclusive<u32> {
+        match self {
+            Family::D3 => 21..=30,
+            Family::D4 => 39..=48,
+            Family::D5 => 72..=80,
+            Family::RareEarth => 57..=71,
+        }
+    }
+
+    /// Directory name under the export root.
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            Family::D3 => "3d",
+            Family::D4 => "4d",
+            Family::D5 => "5d",
+            Family::RareEarth => "rare_earth",
+        }
+    }
+
+    pub fn of(element: &Element) -> Option<Family> {
+        Family::ALL
+            .into_iter()
+            .find(|family| family.atomic_numbers().contains(&element.atomic_number))
+    }
+}
+
+impl FromStr for Family {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Family::ALL
+            .into_iter()
+            .find(|family| family.dir_name() == s)
+            .ok_or_else(|| format!("Unknown family {}, expected 3d, 4d, 5d or rare_earth", s))
+    }
+}
+
+/// Export directory of an element: its family, or `else` outside the families.
+pub fn family_dir(element: &Element) -> &'static str {
+    Family::of(element).map_or("else", |family| family.dir_name())
+}
+
+pub fn period(atomic_number: u32) -> u32 {
+    match atomic_number {
+        0..=2 => 1,
+        3..=10 => 2,
+        11..=18 => 3,
+        19..=36 => 4,
+        37..=54 => 5,
+        55..=86 => 6,
+        _ => 7,
+    }
+}
+
+/// IUPAC group (1-18); `None` for the lanthanides and actinides.
+pub fn group(atomic_number: u32) -> Option<u32> {
+    let pos = match atomic_number {
+        1 => return Some(1),
+        2 => return Some(18),
+        3..=18 => {
+            let pos = (atomic_number - 3) % 8;
+            return Some(if pos < 2 { pos + 1 } else { pos + 11 });
+        }
+        19..=54 => return Some((atomic_number - 19) % 18 + 1),
+        _ => (atomic_number - 55) % 32,
+    };
+    match pos {
+        0 | 1 => Some(pos + 1),
+        2..=16 => None,
+        _ => Some(pos - 13),
+    }
+}
+
+pub fn element_by_symbol(symbol: &str) -> Result<&'static Element, UnknownSymbolError> {
+    pt::periodic_table()
+        .iter()
+        .find(|elm| elm.symbol == symbol)
+        .copied()
+        .ok_or_else(|| UnknownSymbolError(symbol.to_string()))
+}
+
+/**
+Union of the listed families, periods, groups and symbols, minus `exclude`.
+Without any entry the four families are selected, i.e. the 44 metals of the original setup.
+# Example:
+```yaml
+elements:
+  families: [3d, 5d]
+  groups: [10, 11]
+  symbols: [Ce]
+  exclude: [Hg, Cd]
+```
+*/
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ElementSelection {
+    #[serde(default)]
+    families: Vec<Family>,
+    #[serde(default)]
+    periods: Vec<u32>,
+    #[serde(default)]
+    groups: Vec<u32>,
+    #[serde(default)]
+    symbols: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+impl ElementSelection {
+    pub fn families(&self) -> &[Family] {
+        self.families.as_ref()
+    }
+
+    pub fn periods(&self) -> &[u32] {
+        self.periods.as_ref()
+    }
+
+    pub fn groups(&self) -> &[u32] {
+        self.groups.as_ref()
+    }
+
+    pub fn symbols(&self) -> &[String] {
+        self.symbols.as_ref()
+    }
+
+    pub fn exclude(&self) -> &[String] {
+        self.exclude.as_ref()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.families.is_empty()
+            && self.periods.is_empty()
+            && self.groups.is_empty()
+            && self.symbols.is_empty()
+    }
+
+    /**
+    Resolve the selection against the periodic table.
+    # Returns:
+    - Elements ordered by family, then periods, groups and symbols as listed, without duplicates.
+    */
+    pub fn select(&self) -> Result<Vec<&'static Element>, UnknownSymbolError> {
+        let table: &[&Element] = pt::periodic_table();
+        let families: &[Family] = if self.is_empty() {
+            &Family::ALL
+        } else {
+            &self.families
+        };
+        let mut selected: Vec<&Element> = vec![];
+        families.iter().for_each(|family| {
+            selected.extend(
+                table
+                    .iter()
+                    .filter(|elm| family.atomic_numbers().contains(&elm.atomic_number)),
+            )
+        });
+        self.periods.iter().for_each(|&p| {
+            selected.extend(table.iter().filter(|elm| period(elm.atomic_number) == p))
+        });
+        self.groups.iter().for_each(|&g| {
+            selected.extend(
+                table
+                    .iter()
+                    .filter(|elm| group(elm.atomic_number) == Some(g)),
+            )
+        });
+        for symbol in self.symbols.iter() {
+            selected.push(element_by_symbol(symbol)?);
+        }
+        for symbol in self.exclude.iter() {
+            element_by_symbol(symbol)?;
+        }
+        let mut unique: Vec<&Element> = vec![];
+        selected.into_iter().for_each(|elm| {
+            if !unique.iter().any(|u| u.atomic_number == elm.atomic_number)
+                && !self.exclude.iter().any(|symbol| symbol == elm.symbol)
+            {
+                unique.push(elm)
+            }
+        });
+        Ok(unique)
+    }
+}
+
+/**
+Narrow `elements` from the command line: keep only `symbols` and `families` when given,
+then drop `exclude`.
+*/
+pub fn narrow_selection(
+    elements: Vec<&'static Element>,
+    symbols: &[String],
+    families: &[Family],
+    exclude: &[String],
+) -> Vec<&'static Element> {
+    elements
+        .into_iter()
+        .filter(|elm| symbols.is_empty() || symbols.iter().any(|s| s == elm.symbol))
+        .filter(|elm| {
+            families.is_empty() || Family::of(elm).is_some_and(|family| families.contains(&family))
+        })
+        .filter(|elm| !exclude.iter().any(|s| s == elm.symbol))
+        .collect()
+}
+
+/**
+Whether one of `elements` sits on a substitution site of the model,
+read from the atoms of the lattice rather than its name, so any naming template works.
+# Returns:
+- Error if a site atom is missing from the lattice.
+*/
+pub fn model_has_element(
+    lattice: &Lattice,
+    sites: &[SubstitutionSite],
+    elements: &[&Element],
+) -> Result<bool, MissingAtomIdError> {
+    for site in sites {
+        let atom = lattice
+            .atoms_vec()
+            .get_atom_by_id(site.atom_id())
+            .ok_or_else(|| MissingAtomIdError::new(site.atom_id(), lattice.lattice_name()))?;
+        if elements.iter().any(|elm| elm.symbol == atom.element_name()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+#[test]
+fn test_element_selection() {
+    let all = ElementSelection::default().select().unwrap();
+    assert_eq!(all.len(), 44);
+    assert_eq!(all[0].symbol, "Sc");
+    assert_eq!((period(26), group(26)), (4, Some(8)));
+    assert_eq!((group(8), group(79), group(58)), (Some(16), Some(11), None));
+    let selection: ElementSelection =
+        serde_yaml::from_str("{families: [4d], periods: [2], symbols: [Ce], exclude: [Cd, Ne]}")
+            .unwrap();
+    let symbols: Vec<&str> = selection
+        .select()
+        .unwrap()
+        .iter()
+        .map(|elm| elm.symbol)
+        .collect();
+    assert_eq!(
+        symbols,
+        vec![
+            "Y", "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Li", "Be", "B", "C", "N", "O",
+            "F", "Ce"
+        ]
+    );
+    assert_eq!(family_dir(element_by_symbol("Ce").unwrap()), "rare_earth");
+    let narrowed = narrow_selection(all, &[], &["5d".parse().unwrap()], &["Hg".to_string()]);
+    assert_eq!(narrowed.len(), 8);
+    let sites: Vec<SubstitutionSite> = serde_yaml::from_str(
+        "[{name: M1, atom_id: 1, group: A}, {name: M2, atom_id: 2, group: A}, {name: M3, atom_id: 3, group: B}]",
+    )
+    .unwrap();
+    let model = |name: &str, metals: [&str; 3]| {
+        crate::structure_import::build_lattice(
+            name,
+            nalgebra::Matrix3::identity() * 10.0,
+            metals
+                .iter()
+                .enumerate()
+                .map(|(i, elm)| (elm.to_string(), nalgebra::Point3::new(i as f64, 0.0, 0.0)))
+                .collect(),
+        )
+        .unwrap()
+    };
+    // Custom naming templates such as `TAC_{A}2{B}` do not split into symbols.
+    let pt_model = model("TAC_Fe2Pt", ["Fe", "Fe", "Pt"]);
+    assert!(model_has_element(&pt_model, &sites, &narrowed).unwrap());
+    assert!(
+        !model_has_element(&model("TAC_Fe2Co", ["Fe", "Fe", "Co"]), &sites, &narrowed).unwrap()
+    );
+    let far_site: Vec<SubstitutionSite> =
+        serde_yaml::from_str("[{name: M4, atom_id: 4, group: C}]").unwrap();
+    assert!(model_has_element(&pt_model, &far_site, &narrowed).is_err());
+}
@@ -0,0 +1,79 @@
+use std::{error::Error, fmt::Display, fs};
+
+/**
+Define Structs to deserialize `element_table.yaml`, which carries the CASTEP
+settings (mass, pseudopotential, LCAO states, initial spin) of each element.
+*/
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct ElementTable {
+    #[serde(rename = "Element_info")]
+    element_info: Vec<ElementInfo>,
+}
+
+impl ElementTable {
+    pub fn element_info(&self) -> &[ElementInfo] {
+        self.element_info.as_ref()
+    }
+    pub fn get_element(&self, symbol: &str) -> Result<&ElementInfo, UnknownElementError> {
+        self.element_info
+            .iter()
+            .find(|info| info.element() == symbol)
+            .ok_or_else(|| UnknownElementError(symbol.to_string()))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ElementInfo {
+    element: String,
+    atomic_num: u32,
+    #[serde(rename = "LCAO")]
+    lcao: u32,
+    mass: f64,
+    pot: String,
+    spin: u32,
+}
+
+impl ElementInfo {
+    pub fn element(&self) -> &str {
+        self.element.as_ref()
+    }
+
+    pub fn atomic_num(&self) -> u32 {
+        self.atomic_num
+    }
+
+    pub fn lcao(&self) -> u32 {
+        self.lcao
+    }
+
+    pub fn mass(&self) -> f64 {
+        self.mass
+    }
+
+    pub fn pot(&self) -> &str {
+        self.pot.as_ref()
+    }
+
+    pub fn spin(&self) -> u32 {
+        self.spin
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnknownElementError(pub String);
+
+impl Display for UnknownElementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Element {} is not listed in the element table", self.0)
+    }
+}
+
+impl Error for UnknownElementError {}
+
+pub fn load_element_table(filename: &str) -> Result<ElementTable, Box<dyn Error>> {
+    let text = fs::read_to_string(filename)?;
+    let table: ElementTable = serde_yaml::from_str(&text)?;
+    Ok(table)
+}
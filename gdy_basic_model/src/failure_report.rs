@@ -0,0 +1,213 @@
+use std::{error::Error, fmt::Display, io};
+
+use crate::{
+    editor::gdy_tri_editor::MissingAtomIdError,
+    manifest::{ManifestEntry, WriteStatus},
+};
+
+/// Why one model could not be generated.
+#[derive(Debug, Clone)]
+pub enum ModelError {
+    MissingAtomId(MissingAtomIdError),
+    Io(String),
+    /// Rejected by the geometric checks, see `validation::validate_lattice`.
+    Validation(Vec<String>),
+    Other(String),
+}
+
+impl ModelError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ModelError::MissingAtomId(_) => "missing atom id",
+            ModelError::Io(_) => "io",
+            ModelError::Validation(_) => "validation",
+            ModelError::Other(_) => "other",
+        }
+    }
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelError::MissingAtomId(e) => write!(f, "{}", e),
+            ModelError::Io(msg) | ModelError::Other(msg) => write!(f, "{}", msg),
+            ModelError::Validation(issues) => write!(f, "{}", issues.join("; ")),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+impl From<Box<dyn Error>> for ModelError {
+    fn from(e: Box<dyn Error>) -> Self {
+        let e = match e.downcast::<MissingAtomIdError>() {
+            Ok(e) => return ModelError::MissingAtomId(*e),
+            Err(e) => e,
+        };
+        match e.downcast::<io::Error>() {
+            Ok(e) => ModelError::Io(e.to_string()),
+            Err(e) => ModelError::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<MissingAtomIdError> for ModelError {
+    fn from(e: MissingAtomIdError) -> Self {
+        ModelError::MissingAtomId(e)
+    }
+}
+
+impl From<String> for ModelError {
+    fn from(msg: String) -> Self {
+        ModelError::Other(msg)
+    }
+}
+
+/// A model left out of the run, and why.
+#[derive(Debug, Clone)]
+pub struct ModelFailure {
+    model: String,
+    error: ModelError,
+}
+
+impl ModelFailure {
+    pub fn new(model: &str, error: impl Into<ModelError>) -> Self {
+        Self {
+            model: model.to_string(),
+            error: error.into(),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        self.model.as_ref()
+    }
+
+    pub fn error(&self) -> &ModelError {
+        &self.error
+    }
+}
+
+/// Failures gathered over a generation run, which goes on with the remaining models.
+#[derive(Debug, Clone, Default)]
+pub struct FailureReport {
+    failures: Vec<ModelFailure>,
+}
+
+impl FailureReport {
+    /**
+    Split the per-model results of a run.
+    # Returns:
+    - (entries for the manifest, failed models); the `rejected` entries are kept in the
+      manifest and reported as validation failures.
+    */
+    pub fn collect(
+        results: Vec<Result<ManifestEntry, ModelFailure>>,
+    ) -> (Vec<ManifestEntry>, FailureReport) {
+        let mut entries: Vec<ManifestEntry> = vec![];
+        let mut report = FailureReport::default();
+        results.into_iter().for_each(|result| match result {
+            Ok(entry) => {
+                if entry.status() == WriteStatus::Rejected {
+                    report.push(ModelFailure::new(
+                        entry.model(),
+                        ModelError::Validation(entry.issues().to_vec()),
+                    ));
+                }
+                entries.push(entry)
+            }
+            Err(failure) => report.push(failure),
+        });
+        (entries, report)
+    }
+
+    pub fn push(&mut self, failure: ModelFailure) {
+        self.failures.push(failure)
+    }
+
+    pub fn extend(&mut self, other: FailureReport) {
+        self.failures.extend(other.failures)
+    }
+
+    pub fn failures(&self) -> &[ModelFailure] {
+        self.failures.as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Number of failures of `kind`, see `ModelError::kind`.
+    pub fn count(&self, kind: &str) -> usize {
+        self.failures
+            .iter()
+            .filter(|failure| failure.error().kind() == kind)
+            .count()
+    }
+
+    /// One row per failed model, followed by the count of each kind of error.
+    pub fn table(&self) -> String {
+        let width = self
+            .failures
+            .iter()
+            .map(|failure| failure.model().len())
+            .max()
+            .unwrap_or(0)
+            .max("model".len());
+        let mut lines: Vec<String> = vec![
+            format!("{} models failed", self.failures.len()),
+            format!("{:<width$}  {:<15}  detail", "model", "error"),
+        ];
+        self.failures.iter().for_each(|failure| {
+            lines.push(format!(
+                "{:<width$}  {:<15}  {}",
+                failure.model(),
+                failure.error().kind(),
+                failure.error()
+            ))
+        });
+        lines.push(
+            ["missing atom id", "io", "validation", "other"]
+                .iter()
+                .map(|kind| format!("{}: {}", kind, self.count(kind)))
+                .collect::<Vec<String>>()
+                .join(", "),
+        );
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_failure_report() {
+    use std::path::Path;
+
+    let io_error: Box<dyn Error> = Box::new(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "permission denied",
+    ));
+    let missing: Box<dyn Error> = Box::new(MissingAtomIdError::new(73, "GDY_Fe_Fe_Co"));
+    let rejected = ManifestEntry::new(
+        "GDY_Co_Co_Co",
+        vec!["Co".to_string(); 3],
+        Path::new("GDY_Co_Co_Co.msi"),
+        String::new(),
+        WriteStatus::Rejected,
+        vec!["atoms 73 and 74 are 0.500 A apart (minimum 1.764 A)".to_string()],
+    );
+    let results = vec![
+        Err(ModelFailure::new("GDY_Fe_Fe_Co", missing)),
+        Err(ModelFailure::new("GDY_Ni_Ni_Cu", io_error)),
+        Ok(rejected),
+        Err(ModelFailure::new("GDY_Zn_Zn_Cu", "bad cell".to_string())),
+    ];
+    let (entries, report) = FailureReport::collect(results);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(report.failures().len(), 4);
+    assert_eq!(report.count("missing atom id"), 1);
+    assert_eq!(report.count("io"), 1);
+    assert_eq!(report.count("validation"), 1);
+    assert_eq!(report.count("other"), 1);
+    let table = report.table();
+    assert!(table.starts_with("4 models failed"));
+    assert!(table.contains("GDY_Ni_Ni_Cu  io               permission denied"));
+}
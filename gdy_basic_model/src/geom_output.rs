@@ -0,0 +1,328 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use castep_model_generator_backend::lattice::Lattice;
+use nalgebra::{Matrix3, Point3, Vector3};
+
+use crate::{
+    job_script::seed_name_in,
+    structure_import::{build_lattice, BOHR_TO_ANGSTROM},
+};
+
+pub const HARTREE_TO_EV: f64 = 27.211386245988;
+
+#[derive(Debug, Clone)]
+pub struct ParseGeomError(pub String);
+
+impl Display for ParseGeomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse .geom output: {}", self.0)
+    }
+}
+
+impl Error for ParseGeomError {}
+
+/// One step of a `.geom` trajectory, converted to Angstrom and eV.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeomFrame {
+    step: u32,
+    /// Enthalpy of the step, in eV.
+    energy: f64,
+    /// Lattice vectors as columns, in Angstrom.
+    cell: Matrix3<f64>,
+    /// (element, cartesian position in Angstrom), in file order.
+    positions: Vec<(String, Point3<f64>)>,
+    /// Forces in eV/Angstrom, aligned with `positions`.
+    forces: Vec<Vector3<f64>>,
+}
+
+impl GeomFrame {
+    pub fn step(&self) -> u32 {
+        self.step
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.energy
+    }
+
+    pub fn cell(&self) -> &Matrix3<f64> {
+        &self.cell
+    }
+
+    pub fn positions(&self) -> &[(String, Point3<f64>)] {
+        self.positions.as_ref()
+    }
+
+    pub fn forces(&self) -> &[Vector3<f64>] {
+        self.forces.as_ref()
+    }
+
+    /// Largest force on an atom, in eV/Angstrom.
+    pub fn max_force(&self) -> f64 {
+        self.forces
+            .iter()
+            .map(|force| force.norm())
+            .fold(0.0, f64::max)
+    }
+
+    /// The frame as a lattice named `name`, with atom ids 1..N in file order.
+    pub fn to_lattice(&self, name: &str) -> Result<Lattice, Box<dyn Error>> {
+        build_lattice(name, self.cell, self.positions.clone())
+    }
+}
+
+pub fn read_geom<P: AsRef<Path>>(path: P) -> Result<Vec<GeomFrame>, Box<dyn Error>> {
+    Ok(parse_geom(&fs::read_to_string(path)?)?)
+}
+
+/// Numbers of a `.geom` line before its `<--` tag.
+fn tagged_values(line: &str, skip: usize) -> Result<Vec<f64>, ParseGeomError> {
+    line.split("<--")
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .skip(skip)
+        .map(|value| {
+            value
+                .parse::<f64>()
+                .map_err(|_| ParseGeomError(format!("bad value in line '{}'", line.trim())))
+        })
+        .collect()
+}
+
+fn vector(values: &[f64], line: &str) -> Result<Vector3<f64>, ParseGeomError> {
+    match values {
+        [x, y, z, ..] => Ok(Vector3::new(*x, *y, *z)),
+        _ => Err(ParseGeomError(format!(
+            "expected 3 values in line '{}'",
+            line.trim()
+        ))),
+    }
+}
+
+/**
+Parse the frames of a `.geom` file. Each frame starts with the step line tagged `<-- c`,
+followed by the energy (`<-- E`), the lattice vectors (`<-- h`), the positions (`<-- R`)
+and the forces (`<-- F`) in atomic units; the stress (`<-- S`) and velocities are ignored.
+*/
+pub fn parse_geom(text: &str) -> Result<Vec<GeomFrame>, ParseGeomError> {
+    let mut frames: Vec<GeomFrame> = vec![];
+    let mut vectors: Vec<Vector3<f64>> = vec![];
+    for line in text.lines() {
+        let tag = match line.split("<--").nth(1) {
+            Some(tag) => tag.trim(),
+            None => continue,
+        };
+        if tag == "c" {
+            let step = line
+                .split_whitespace()
+                .next()
+                .and_then(|step| step.parse::<u32>().ok())
+                .ok_or_else(|| ParseGeomError(format!("bad step line '{}'", line.trim())))?;
+            frames.push(GeomFrame {
+                step,
+                energy: 0.0,
+                cell: Matrix3::zeros(),
+                positions: vec![],
+                forces: vec![],
+            });
+            vectors.clear();
+            continue;
+        }
+        let frame = frames
+            .last_mut()
+            .ok_or_else(|| ParseGeomError(format!("'{}' before the first step", line.trim())))?;
+        match tag {
+            "E" => {
+                frame.energy = tagged_values(line, 0)?
+                    .first()
+                    .copied()
+                    .ok_or_else(|| ParseGeomError(format!("no energy in '{}'", line.trim())))?
+                    * HARTREE_TO_EV
+            }
+            "h" => {
+                vectors.push(vector(&tagged_values(line, 0)?, line)? * BOHR_TO_ANGSTROM);
+                if vectors.len() == 3 {
+                    frame.cell = Matrix3::from_columns(&vectors);
+                }
+            }
+            "R" => {
+                let element = line.split_whitespace().next().unwrap_or_default();
+                let xyz = vector(&tagged_values(line, 2)?, line)? * BOHR_TO_ANGSTROM;
+                frame
+                    .positions
+                    .push((element.to_string(), Point3::from(xyz)));
+            }
+            "F" => {
+                let force = vector(&tagged_values(line, 2)?, line)?;
+                frame.forces.push(force * HARTREE_TO_EV / BOHR_TO_ANGSTROM);
+            }
+            _ => {}
+        }
+    }
+    Ok(frames)
+}
+
+/**
+Multi-frame extended XYZ of the trajectory, one frame per step with the lattice, the step,
+the energy (eV) and the forces (eV/Angstrom) of the step, e.g. for `ase gui` or OVITO.
+*/
+pub fn extxyz_frames_text(frames: &[GeomFrame], name: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    frames.iter().for_each(|frame| {
+        let vectors: Vec<String> = frame
+            .cell
+            .iter()
+            .map(|x| format!("{:.10}", x))
+            .collect();
+        lines.push(frame.positions.len().to_string());
+        lines.push(format!(
+            "Lattice=\"{}\" Properties=species:S:1:pos:R:3:forces:R:3 energy={:.8} step={} pbc=\"T T T\" name={}",
+            vectors.join(" "),
+            frame.energy,
+            frame.step,
+            name
+        ));
+        frame
+            .positions
+            .iter()
+            .zip(frame.forces.iter().chain(std::iter::repeat(&Vector3::zeros())))
+            .for_each(|((element, xyz), force)| {
+                lines.push(format!(
+                    "{:<4}{:20.15}{:20.15}{:20.15}{:16.8}{:16.8}{:16.8}",
+                    element, xyz.x, xyz.y, xyz.z, force.x, force.y, force.z
+                ))
+            });
+    });
+    lines.join("\n") + "\n"
+}
+
+/// Convergence of the trajectory as CSV: `step,energy,delta_energy,max_force` in eV and eV/Angstrom.
+pub fn step_table(frames: &[GeomFrame]) -> String {
+    let mut lines = vec!["step,energy,delta_energy,max_force".to_string()];
+    let mut previous: Option<f64> = None;
+    frames.iter().for_each(|frame| {
+        lines.push(format!(
+            "{},{:.8},{:.8},{:.8}",
+            frame.step,
+            frame.energy,
+            previous.map(|energy| frame.energy - energy).unwrap_or(0.0),
+            frame.max_force()
+        ));
+        previous = Some(frame.energy);
+    });
+    lines.join("\n") + "\n"
+}
+
+/**
+Write the trajectory of the `.geom` in `seed_dir` next to it, as `{seed}_traj.xyz` and `{seed}_steps.csv`.
+# Returns:
+- (extended XYZ path, step table path, number of frames)
+*/
+pub fn write_trajectory(seed_dir: &Path) -> Result<(PathBuf, PathBuf, usize), Box<dyn Error>> {
+    let seed_name = seed_name_in(seed_dir)?;
+    let frames = read_geom(seed_dir.join(format!("{}.geom", seed_name)))?;
+    if frames.is_empty() {
+        return Err(Box::new(ParseGeomError(format!(
+            "no step in {}.geom",
+            seed_name
+        ))));
+    }
+    let xyz_path = seed_dir.join(format!("{}_traj.xyz", seed_name));
+    let table_path = seed_dir.join(format!("{}_steps.csv", seed_name));
+    fs::write(&xyz_path, extxyz_frames_text(&frames, &seed_name))?;
+    fs::write(&table_path, step_table(&frames))?;
+    Ok((xyz_path, table_path, frames.len()))
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_geom() {
+    let text = " BEGIN header
+
+ END header
+
+                                      0                                                                 F   F   F   T         <-- c
+                    -1.1698551e+002                    -1.1698551e+002                                             <-- E
+                     1.8897261e+001     0.0000000e+000     0.0000000e+000                                            <-- h
+                     0.0000000e+000     1.8897261e+001     0.0000000e+000                                            <-- h
+                     0.0000000e+000     0.0000000e+000     3.7794522e+001                                            <-- h
+ C              1    0.0000000e+000     0.0000000e+000     9.4486306e+000                                            <-- R
+ Fe             1    1.8897261e+000     0.0000000e+000     9.4486306e+000                                            <-- R
+ C              1    0.0000000e+000     0.0000000e+000     1.0000000e-002                                            <-- F
+ Fe             1    0.0000000e+000     0.0000000e+000    -1.0000000e-002                                            <-- F
+
+                                      1                                                                 F   F   F   T         <-- c
+                    -1.1700000e+002                    -1.1700000e+002                                             <-- E
+                     1.8897261e+001     0.0000000e+000     0.0000000e+000                                            <-- h
+                     0.0000000e+000     1.8897261e+001     0.0000000e+000                                            <-- h
+                     0.0000000e+000     0.0000000e+000     3.7794522e+001                                            <-- h
+ C              1    0.0000000e+000     0.0000000e+000     9.4486306e+000                                            <-- R
+ Fe             1    2.0786987e+000     0.0000000e+000     9.4486306e+000                                            <-- R
+ C              1    0.0000000e+000     0.0000000e+000     0.0000000e+000                                            <-- F
+ Fe             1    0.0000000e+000     0.0000000e+000     0.0000000e+000                                            <-- F
+";
+    let frames = parse_geom(text).unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].step(), 0);
+    assert!((frames[0].energy() - -116.98551 * HARTREE_TO_EV).abs() < 1e-8);
+    assert!((frames[0].cell()[(0, 0)] - 10.0).abs() < 1e-6);
+    assert!((frames[0].cell()[(2, 2)] - 20.0).abs() < 1e-6);
+    assert!((frames[1].positions()[1].1.x - 1.1).abs() < 1e-6);
+    assert!((frames[0].max_force() - 0.01 * HARTREE_TO_EV / BOHR_TO_ANGSTROM).abs() < 1e-8);
+    let lattice = frames[1].to_lattice("GDY_Fe").unwrap();
+    assert_eq!(lattice.atoms_vec()[1].element_name(), "Fe");
+    assert_eq!(lattice.atoms_vec()[1].atom_id(), 2);
+    let xyz = extxyz_frames_text(&frames, "GDY_Fe");
+    let lines: Vec<&str> = xyz.lines().collect();
+    assert_eq!(lines.len(), 8);
+    // One frame of atom count, header and atoms per `.geom` step.
+    let headers: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|line| line.starts_with("Lattice="))
+        .collect();
+    assert_eq!(headers.len(), frames.len());
+    assert_eq!((lines[0], lines[4]), ("2", "2"));
+    for (header, frame) in headers.iter().zip(frames.iter()) {
+        let lattice: Vec<f64> = header
+            .split('"')
+            .nth(1)
+            .unwrap()
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        assert_eq!(lattice.len(), 9);
+        assert!((lattice[0] - 10.0).abs() < 1e-6 && (lattice[8] - 20.0).abs() < 1e-6);
+        assert!(lattice
+            .iter()
+            .enumerate()
+            .all(|(i, x)| i % 4 == 0 || x.abs() < 1e-12));
+        assert!(header.contains(&format!(
+            " energy={:.8} step={} ",
+            frame.energy(),
+            frame.step()
+        )));
+    }
+    assert!(lines[7].starts_with("Fe  "));
+    let table = step_table(&frames);
+    let rows: Vec<Vec<&str>> = table.lines().map(|row| row.split(',').collect()).collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0], ["step", "energy", "delta_energy", "max_force"]);
+    let column = |i: usize| -> Vec<f64> {
+        rows[1..]
+            .iter()
+            .map(|row| row[i].parse().unwrap())
+            .collect()
+    };
+    assert_eq!(column(0), [0.0, 1.0]);
+    let (delta_energy, max_force) = (column(2), column(3));
+    assert_eq!(delta_energy[0], 0.0);
+    assert!((delta_energy[1] - (-117.0 + 116.98551) * HARTREE_TO_EV).abs() < 1e-6);
+    assert!((max_force[0] - 0.51422067).abs() < 1e-8);
+    assert_eq!(max_force[1], 0.0);
+}
@@ -0,0 +1,252 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use glob::glob;
+use serde::Deserialize;
+
+use crate::manifest::{write_with_policy, WriteOptions, WriteStatus};
+
+/// Matches the wrapper scripts of the LSF cluster, formerly written by `write_lsf_script.py`.
+const LSF_TEMPLATE: &str = r#"APP_NAME={queue}
+NP={cores}
+NP_PER_NODE={cores_per_node}
+OMP_NUM_THREADS=1
+RUN="RAW"
+
+{executable} -np $NP {seed}"#;
+
+const SLURM_TEMPLATE: &str = r#"#!/bin/bash
+#SBATCH --job-name={job_name}
+#SBATCH --partition={queue}
+#SBATCH --nodes={nodes}
+#SBATCH --ntasks={cores}
+#SBATCH --ntasks-per-node={cores_per_node}
+
+export OMP_NUM_THREADS=1
+{executable} -np {cores} {seed}
+"#;
+
+const PBS_TEMPLATE: &str = r#"#!/bin/bash
+#PBS -N {job_name}
+#PBS -q {queue}
+#PBS -l nodes={nodes}:ppn={cores_per_node}
+
+cd $PBS_O_WORKDIR
+export OMP_NUM_THREADS=1
+{executable} -np {cores} {seed}
+"#;
+
+/// Batch system dialect of the job scripts.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheduler {
+    Lsf,
+    Slurm,
+    Pbs,
+}
+
+impl Scheduler {
+    /// Built-in template of the dialect.
+    pub fn default_template(&self) -> &'static str {
+        match self {
+            Scheduler::Lsf => LSF_TEMPLATE,
+            Scheduler::Slurm => SLURM_TEMPLATE,
+            Scheduler::Pbs => PBS_TEMPLATE,
+        }
+    }
+    pub fn default_script_name(&self) -> &'static str {
+        match self {
+            Scheduler::Lsf => "MS70_YW_CASTEP.lsf",
+            Scheduler::Slurm => "castep.slurm",
+            Scheduler::Pbs => "castep.pbs",
+        }
+    }
+}
+
+/**
+Settings of the job scripts, from the `job_script` entry of `project.yaml`.
+# Template placeholders:
+`{seed}`, `{job_name}`, `{queue}`, `{cores}`, `{cores_per_node}`, `{nodes}`, `{executable}`
+
+`cores` and `cores_per_node` must be positive; zero is rejected when the project is loaded.
+*/
+#[derive(Deserialize, Debug, Clone)]
+pub struct JobScriptSettings {
+    scheduler: Scheduler,
+    queue: String,
+    cores: NonZeroU32,
+    cores_per_node: Option<NonZeroU32>,
+    executable: String,
+    /// File name of the script in each seed directory; defaults per scheduler.
+    script_name: Option<String>,
+    /// Path to a custom template replacing the built-in one.
+    template: Option<String>,
+}
+
+impl JobScriptSettings {
+    pub fn scheduler(&self) -> Scheduler {
+        self.scheduler
+    }
+
+    pub fn queue(&self) -> &str {
+        self.queue.as_ref()
+    }
+
+    pub fn cores(&self) -> u32 {
+        self.cores.get()
+    }
+
+    pub fn cores_per_node(&self) -> u32 {
+        self.cores_per_node.unwrap_or(self.cores).get()
+    }
+
+    pub fn executable(&self) -> &str {
+        self.executable.as_ref()
+    }
+
+    pub fn script_name(&self) -> &str {
+        self.script_name
+            .as_deref()
+            .unwrap_or_else(|| self.scheduler.default_script_name())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoSeedError(pub PathBuf);
+
+impl Display for NoSeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No .param seed file found in {}", self.0.display())
+    }
+}
+
+impl Error for NoSeedError {}
+
+/// Renders and writes job scripts from the settings and the resolved template.
+#[derive(Debug, Clone)]
+pub struct JobScriptWriter {
+    settings: JobScriptSettings,
+    template: String,
+}
+
+impl JobScriptWriter {
+    pub fn new(settings: &JobScriptSettings) -> Result<Self, Box<dyn Error>> {
+        let template = match &settings.template {
+            Some(path) => fs::read_to_string(path)?,
+            None => settings.scheduler().default_template().to_string(),
+        };
+        Ok(Self {
+            settings: settings.clone(),
+            template,
+        })
+    }
+
+    pub fn settings(&self) -> &JobScriptSettings {
+        &self.settings
+    }
+
+    pub fn render(&self, seed_name: &str) -> String {
+        let cores = self.settings.cores();
+        let cores_per_node = self.settings.cores_per_node();
+        let nodes = cores.div_ceil(cores_per_node);
+        self.template
+            .replace("{seed}", seed_name)
+            .replace("{job_name}", seed_name)
+            .replace("{queue}", self.settings.queue())
+            .replace("{cores}", &cores.to_string())
+            .replace("{cores_per_node}", &cores_per_node.to_string())
+            .replace("{nodes}", &nodes.to_string())
+            .replace("{executable}", self.settings.executable())
+    }
+
+    /// Write the script for `seed_name` into `seed_dir`.
+    pub fn write_script(
+        &self,
+        seed_dir: &Path,
+        seed_name: &str,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let script_path = seed_dir.join(self.settings.script_name());
+        fs::write(&script_path, self.render(seed_name))?;
+        Ok(script_path)
+    }
+
+    /// Write the script for `seed_name` into `seed_dir` under the overwrite policy of `options`.
+    pub fn write_script_with_policy(
+        &self,
+        seed_dir: &Path,
+        seed_name: &str,
+        options: &WriteOptions,
+    ) -> Result<(PathBuf, WriteStatus), Box<dyn Error>> {
+        let script_path = seed_dir.join(self.settings.script_name());
+        let (status, _) = write_with_policy(&script_path, &self.render(seed_name), options)?;
+        Ok((script_path, status))
+    }
+
+    /**
+    Write scripts in every directory under `root_dir` whose name matches `dir_pattern`
+    (e.g. `*_opt`), under the overwrite policy of `options`.
+    The seed name is taken from the `.param` file in the directory.
+    # Returns:
+    - (script path, status) of every directory.
+    */
+    pub fn write_scripts_in(
+        &self,
+        root_dir: &str,
+        dir_pattern: &str,
+        options: &WriteOptions,
+    ) -> Result<Vec<(PathBuf, WriteStatus)>, Box<dyn Error>> {
+        seed_dirs_in(root_dir, dir_pattern)?
+            .iter()
+            .map(|dir| {
+                let seed_name = seed_name_in(dir)?;
+                self.write_script_with_policy(dir, &seed_name, options)
+            })
+            .collect()
+    }
+}
+
+/// Directories under `root_dir`, at any depth, whose name matches `dir_pattern`.
+pub fn seed_dirs_in(root_dir: &str, dir_pattern: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let pattern = format!("{}/**/{}", root_dir, dir_pattern);
+    Ok(glob(&pattern)?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.is_dir())
+        .collect())
+}
+
+/// Seed name of a directory, from the stem of its `.param` file.
+pub fn seed_name_in(dir: &Path) -> Result<String, Box<dyn Error>> {
+    let pattern = format!("{}/*.param", dir.display());
+    let param = glob(&pattern)?
+        .filter_map(|path| path.ok())
+        .next()
+        .ok_or_else(|| NoSeedError(dir.to_path_buf()))?;
+    Ok(param.file_stem().unwrap().to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+#[test]
+fn test_render_job_script() {
+    let settings: JobScriptSettings = serde_yaml::from_str(
+        "{scheduler: slurm, queue: normal, cores: 48, cores_per_node: 24, executable: /opt/castep/RunCASTEP.sh}",
+    )
+    .unwrap();
+    let writer = JobScriptWriter::new(&settings).unwrap();
+    let script = writer.render("GDY_Fe_Fe_Co");
+    assert!(script.contains("#SBATCH --nodes=2"));
+    assert!(script.contains("/opt/castep/RunCASTEP.sh -np 48 GDY_Fe_Fe_Co"));
+    assert_eq!(settings.script_name(), "castep.slurm");
+    assert!(serde_yaml::from_str::<JobScriptSettings>(
+        "{scheduler: pbs, queue: normal, cores: 48, cores_per_node: 0, executable: castep}",
+    )
+    .is_err());
+    assert!(serde_yaml::from_str::<JobScriptSettings>(
+        "{scheduler: pbs, queue: normal, cores: 0, executable: castep}",
+    )
+    .is_err());
+}
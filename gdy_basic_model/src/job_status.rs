@@ -0,0 +1,361 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use glob::glob;
+use rayon::prelude::*;
+
+use crate::{
+    castep_output::{read_castep, CastepOutput, Convergence},
+    failure_report::{FailureReport, ModelFailure},
+    job_script::{seed_dirs_in, seed_name_in},
+    restart::latest_attempt,
+};
+
+/// State of the calculation of one seed directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// No `.castep` output yet.
+    NotSubmitted,
+    /// The `.castep` has no final message yet.
+    Running,
+    Converged,
+    /// The SCF reached `max_scf_cycles` in a run without final message.
+    ScfFailure,
+    /// The geometry optimization stopped at `geom_max_iter`.
+    MaxIterations,
+    /// CASTEP wrote an `.err` file.
+    Error,
+}
+
+impl JobStatus {
+    pub const ALL: [JobStatus; 6] = [
+        JobStatus::NotSubmitted,
+        JobStatus::Running,
+        JobStatus::Converged,
+        JobStatus::ScfFailure,
+        JobStatus::MaxIterations,
+        JobStatus::Error,
+    ];
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            JobStatus::NotSubmitted => "not-submitted",
+            JobStatus::Running => "running",
+            JobStatus::Converged => "converged",
+            JobStatus::ScfFailure => "scf-failure",
+            JobStatus::MaxIterations => "max-iter",
+            JobStatus::Error => "error",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        JobStatus::ALL
+            .iter()
+            .find(|status| status.to_string() == s)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown job status {}, expected not-submitted, running, converged, scf-failure, max-iter or error",
+                    s
+                )
+            })
+    }
+}
+
+/**
+Classify a seed from its `.castep` output and the presence of `.err` files.
+A converged or `geom_max_iter` run is classified by its final message, whatever else is present.
+*/
+pub fn job_status(output: Option<&CastepOutput>, has_err_file: bool) -> JobStatus {
+    match output {
+        None if has_err_file => JobStatus::Error,
+        None => JobStatus::NotSubmitted,
+        Some(output) => match output.convergence() {
+            Convergence::Converged => JobStatus::Converged,
+            Convergence::MaxIterations => JobStatus::MaxIterations,
+            Convergence::Incomplete if has_err_file => JobStatus::Error,
+            Convergence::Incomplete if output.scf_failed() => JobStatus::ScfFailure,
+            Convergence::Incomplete => JobStatus::Running,
+        },
+    }
+}
+
+/// Status of one seed directory.
+#[derive(Debug, Clone)]
+pub struct SeedStatus {
+    seed: String,
+    dir: PathBuf,
+    /// Top directory under the export root: the family, or the adsorbate `directory`.
+    family: String,
+    status: JobStatus,
+    geom_steps: u32,
+    energy: Option<f64>,
+}
+
+impl SeedStatus {
+    pub fn new(seed: &str, dir: &Path, family: &str, status: JobStatus) -> Self {
+        Self {
+            seed: seed.to_string(),
+            dir: dir.to_path_buf(),
+            family: family.to_string(),
+            status,
+            geom_steps: 0,
+            energy: None,
+        }
+    }
+
+    pub fn seed(&self) -> &str {
+        self.seed.as_ref()
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.as_ref()
+    }
+
+    pub fn family(&self) -> &str {
+        self.family.as_ref()
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.status
+    }
+
+    pub fn geom_steps(&self) -> u32 {
+        self.geom_steps
+    }
+
+    pub fn energy(&self) -> Option<f64> {
+        self.energy
+    }
+}
+
+/// Status of the seed in `seed_dir`, a directory under `root_dir`.
+pub fn seed_status(root_dir: &Path, seed_dir: &Path) -> Result<SeedStatus, Box<dyn Error>> {
+    let seed = seed_name_in(seed_dir)?;
+    let family = seed_dir
+        .strip_prefix(root_dir)
+        .ok()
+        .and_then(|path| path.components().next())
+        .map(|family| family.as_os_str().to_string_lossy().to_string())
+        .unwrap_or_default();
+    let castep_path = seed_dir.join(format!("{}.castep", seed));
+    let output = if castep_path.exists() {
+        Some(read_castep(&castep_path)?)
+    } else {
+        None
+    };
+    let has_err_file = glob(&format!("{}/{}*.err", seed_dir.display(), seed))?
+        .filter_map(|path| path.ok())
+        .next()
+        .is_some();
+    let mut seed_status = SeedStatus::new(
+        &seed,
+        seed_dir,
+        &family,
+        job_status(output.as_ref(), has_err_file),
+    );
+    if let Some(output) = output {
+        seed_status.geom_steps = output.geom_steps();
+        seed_status.energy = output.energy();
+    }
+    Ok(seed_status)
+}
+
+/**
+Status of every seed directory matching `dir_pattern` under `root_dir`, classified from its
+latest attempt: the last `_restart_N` directory when the seed has been restarted.
+# Returns:
+- (statuses sorted by directory, directories that could not be read)
+*/
+pub fn scan_seeds(
+    root_dir: &str,
+    dir_pattern: &str,
+) -> Result<(Vec<SeedStatus>, FailureReport), Box<dyn Error>> {
+    let mut dirs = seed_dirs_in(root_dir, dir_pattern)?;
+    dirs.sort();
+    let root = Path::new(root_dir);
+    let results: Vec<Result<SeedStatus, ModelFailure>> = dirs
+        .par_iter()
+        .map(|dir| {
+            let attempt = latest_attempt(dir);
+            seed_status(root, &attempt).map_err(|e| {
+                ModelFailure::new(
+                    &attempt.file_name().unwrap_or_default().to_string_lossy(),
+                    e,
+                )
+            })
+        })
+        .collect();
+    let mut statuses: Vec<SeedStatus> = vec![];
+    let mut report = FailureReport::default();
+    results.into_iter().for_each(|result| match result {
+        Ok(status) => statuses.push(status),
+        Err(failure) => report.push(failure),
+    });
+    Ok((statuses, report))
+}
+
+/// Counts per family and status, one row per family in alphabetical order and a total row.
+pub fn count_table(statuses: &[SeedStatus]) -> String {
+    let mut counts: BTreeMap<&str, [usize; 6]> = BTreeMap::new();
+    let index = |status: JobStatus| JobStatus::ALL.iter().position(|&s| s == status).unwrap();
+    let mut total = [0; 6];
+    statuses.iter().for_each(|seed| {
+        counts.entry(seed.family()).or_insert([0; 6])[index(seed.status())] += 1;
+        total[index(seed.status())] += 1;
+    });
+    let row = |name: &str, counts: &[usize; 6]| {
+        format!(
+            "{:<12}{}",
+            name,
+            counts
+                .iter()
+                .map(|count| format!("{:>15}", count))
+                .collect::<String>()
+        )
+    };
+    let mut lines: Vec<String> = vec![format!(
+        "{:<12}{}",
+        "family",
+        JobStatus::ALL
+            .iter()
+            .map(|status| format!("{:>15}", status.to_string()))
+            .collect::<String>()
+    )];
+    counts
+        .iter()
+        .for_each(|(family, counts)| lines.push(row(family, counts)));
+    lines.push(row("total", &total));
+    lines.join("\n")
+}
+
+/// Write the statuses as CSV: `seed,family,status,geom_steps,energy,dir`.
+pub fn write_status_table(path: &Path, statuses: &[SeedStatus]) -> Result<(), Box<dyn Error>> {
+    let mut lines = vec!["seed,family,status,geom_steps,energy,dir".to_string()];
+    statuses.iter().for_each(|seed| {
+        lines.push(format!(
+            "{},{},{},{},{},{}",
+            seed.seed(),
+            seed.family(),
+            seed.status(),
+            seed.geom_steps(),
+            seed.energy()
+                .map(|energy| format!("{:.8}", energy))
+                .unwrap_or_default(),
+            seed.dir().display()
+        ))
+    });
+    fs::write(path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_job_status() {
+    use crate::castep_output::parse_castep;
+
+    let header = " type of calculation                            : geometry optimization\n";
+    let running = parse_castep(&format!(
+        "{} BFGS: finished iteration     3 with enthalpy= -3.43315359E+003 eV\n",
+        header
+    ))
+    .unwrap();
+    let scf_failure = parse_castep(&format!(
+        "{} *Warning* max. SCF cycles performed but system has not reached the groundstate.\n",
+        header
+    ))
+    .unwrap();
+    let recovered = parse_castep(&format!(
+        "{} *Warning* max. SCF cycles performed but system has not reached the groundstate.\n BFGS: finished iteration     1 with enthalpy= -3.43315359E+003 eV\n",
+        header
+    ))
+    .unwrap();
+    let max_iter = parse_castep(&format!(
+        "{} BFGS: Geometry optimization failed to converge after 100 steps.\n",
+        header
+    ))
+    .unwrap();
+    let converged = parse_castep(&format!(
+        "{} BFGS: Geometry optimization completed successfully.\n",
+        header
+    ))
+    .unwrap();
+    assert_eq!(job_status(None, false), JobStatus::NotSubmitted);
+    assert_eq!(job_status(Some(&running), false), JobStatus::Running);
+    assert_eq!(job_status(Some(&running), true), JobStatus::Error);
+    assert_eq!(job_status(Some(&scf_failure), false), JobStatus::ScfFailure);
+    assert_eq!(job_status(Some(&recovered), false), JobStatus::Running);
+    assert_eq!(job_status(Some(&max_iter), false), JobStatus::MaxIterations);
+    assert_eq!(job_status(Some(&converged), true), JobStatus::Converged);
+
+    let statuses = vec![
+        SeedStatus::new(
+            "GDY_Fe_Fe_Fe",
+            Path::new("3d/Fe"),
+            "3d",
+            JobStatus::Converged,
+        ),
+        SeedStatus::new("GDY_Fe_Fe_Co", Path::new("3d/Fe"), "3d", JobStatus::Running),
+        SeedStatus::new(
+            "GDY_Pt_Pt_Pt",
+            Path::new("5d/Pt"),
+            "5d",
+            JobStatus::Converged,
+        ),
+    ];
+    let table = count_table(&statuses);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[1].split_whitespace().collect::<Vec<&str>>(),
+        ["3d", "0", "1", "1", "0", "0", "0"]
+    );
+    assert_eq!(
+        lines[3].split_whitespace().collect::<Vec<&str>>(),
+        ["total", "0", "1", "2", "0", "0", "0"]
+    );
+
+    let root = std::env::temp_dir().join("gdy_test_scan_seeds");
+    let _ = fs::remove_dir_all(&root);
+    let seed_dir = root.join("3d/Fe/GDY_Fe_opt");
+    let restart_dir = root.join("3d/Fe/GDY_Fe_opt_restart_1");
+    for (dir, output) in [
+        (
+            &seed_dir,
+            " BFGS: Geometry optimization failed to converge after 100 steps.\n",
+        ),
+        (
+            &restart_dir,
+            " BFGS: Geometry optimization completed successfully.\n",
+        ),
+    ] {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("GDY_Fe.param"), "task : GeometryOptimization\n").unwrap();
+        fs::write(dir.join("GDY_Fe.castep"), format!("{}{}", header, output)).unwrap();
+    }
+    let (statuses, report) = scan_seeds(&root.to_string_lossy(), "*_opt").unwrap();
+    assert!(report.is_empty());
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(
+        (
+            statuses[0].status(),
+            statuses[0].dir(),
+            statuses[0].family()
+        ),
+        (JobStatus::Converged, restart_dir.as_path(), "3d")
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -1,3 +1,23 @@
 #![allow(dead_code)]
+pub mod adsorbate;
+pub mod castep_output;
+pub mod collector;
+pub mod combination;
+pub mod d_band;
+pub mod dos_seed;
 pub mod editor;
+pub mod element_selection;
+pub mod element_table;
+pub mod failure_report;
+pub mod geom_output;
+pub mod job_script;
+pub mod job_status;
+pub mod manifest;
+pub mod potentials;
+pub mod project_config;
+pub mod restart;
 pub mod seed_export;
+pub mod structure_export;
+pub mod structure_import;
+pub mod symmetry;
+pub mod validation;
@@ -1,17 +1,572 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 use castep_model_generator_backend::external_info::project::{load_project_info, ProjectInfo};
-fn main() -> Result<(), Box<dyn Error>> {
-    let project_info = load_project_info("./resources/project.yaml")?;
-    task_gen_all(&project_info)?;
-    Ok(())
+use clap::{Args, Parser, Subcommand};
+use gdy_tri_basic_models::adsorbate::{find_base_models, generate_all_ads_models, load_ads_table};
+use gdy_tri_basic_models::collector::{collect_energies, EnergyCell};
+use gdy_tri_basic_models::d_band::{d_band_csv_text, dos_d_band_rows, finished_dos_dirs, DBandRow};
+use gdy_tri_basic_models::dos_seed::write_dos_seed;
+use gdy_tri_basic_models::editor::gdy_tri_editor::generate_all_base_models;
+use gdy_tri_basic_models::element_selection::{model_has_element, narrow_selection, Family};
+use gdy_tri_basic_models::failure_report::{FailureReport, ModelFailure};
+use gdy_tri_basic_models::geom_output::write_trajectory;
+use gdy_tri_basic_models::job_script::{seed_dirs_in, seed_name_in, JobScriptWriter};
+use gdy_tri_basic_models::job_status::{
+    count_table, scan_seeds, seed_status, write_status_table, JobStatus,
+};
+use gdy_tri_basic_models::manifest::{status_summary, OverwritePolicy, WriteOptions, WriteStatus};
+use gdy_tri_basic_models::potentials::PotentialResolver;
+use gdy_tri_basic_models::project_config::{load_gdy_project, GdyProject};
+use gdy_tri_basic_models::restart::{latest_attempt, write_restart, RestartSettings};
+use gdy_tri_basic_models::seed_export::{species_list, SeedExporter};
+use gdy_tri_basic_models::structure_export::{StructureFormat, StructureFormats};
+use gdy_tri_basic_models::structure_import::read_lattice;
+use glob::glob;
+use periodic_table::Element;
+
+/// Generate metal-substituted GDY models, their adsorbate models and CASTEP seeds.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Path to `project.yaml`
+    #[arg(long, global = true, default_value = "./resources/project.yaml")]
+    project: String,
+    /// Report what would be written without touching the disk
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Handling of existing files: skip, overwrite, overwrite-if-differs or backup,
+    /// instead of `overwrite_policy`
+    #[arg(long, global = true)]
+    policy: Option<OverwritePolicy>,
+    /// Replace existing files, same as `--policy overwrite`
+    #[arg(long, global = true, conflicts_with = "policy")]
+    force: bool,
+    /// Structure formats written with the seeds (poscar, extxyz, cif), instead of `structure_formats`
+    #[arg(long, global = true, value_delimiter = ',')]
+    formats: Option<Vec<StructureFormat>>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate the substituted base models
+    GenBase(ElementFilter),
+    /// Place the adsorbates on the generated base models
+    GenAds(ElementFilter),
+    /// (Re)export the CASTEP seeds of the generated `.msi` files
+    ExportSeeds(ElementFilter),
+    /// (Re)write the job scripts of the seed directories
+    WriteScripts {
+        /// Name pattern of the seed directories
+        #[arg(long, default_value = "*_opt")]
+        pattern: String,
+    },
+    /// Classify the seeds as not submitted, running, converged, SCF failure, max-iter or error
+    Status,
+    /// Write `_restart_N` seeds from the last geometry of the stopped calculations
+    Restart {
+        /// Job statuses to restart
+        #[arg(long, value_delimiter = ',', default_value = "max-iter,scf-failure")]
+        statuses: Vec<JobStatus>,
+        /// Continue from the `.check` of the stopped run, besides `restart.continuation`
+        #[arg(long)]
+        continuation: bool,
+    },
+    /// Write the `_DOS` seeds of the converged optimizations from `dos_param_loc`
+    Dos,
+    /// Write the `.geom` trajectories as multi-frame extended XYZ and per-step energy/force tables
+    Trajectory {
+        /// Seed directories, instead of every seed directory under `export_loc`
+        dirs: Vec<PathBuf>,
+    },
+    /// Write the d-band center, width, filling and upper edge of the metal atoms from the `_DOS` outputs
+    DBand,
+    /// Collect the energies of the finished calculations into the CSV of `data_process`
+    Collect,
+}
+
+/// Narrows the `elements` selection of `project.yaml`.
+#[derive(Args, Debug)]
+struct ElementFilter {
+    /// Only these element symbols, e.g. `--elements Fe,Co`
+    #[arg(long, value_delimiter = ',')]
+    elements: Vec<String>,
+    /// Only these families: 3d, 4d, 5d, rare_earth
+    #[arg(long, value_delimiter = ',')]
+    families: Vec<Family>,
+    /// Leave out these element symbols
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<String>,
+}
+
+impl ElementFilter {
+    fn metals(&self, gdy_project: &GdyProject) -> Result<Vec<&'static Element>, Box<dyn Error>> {
+        Ok(narrow_selection(
+            gdy_project.elements().select()?,
+            &self.elements,
+            &self.families,
+            &self.exclude,
+        ))
+    }
+}
+
+/// Exits with a failure code after printing the report when some models could not be generated.
+fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let cli = Cli::parse();
+    let project_info = load_project_info(&cli.project)?;
+    let gdy_project = load_gdy_project(&cli.project)?;
+    let policy = match (cli.force, cli.policy) {
+        (true, _) => OverwritePolicy::Overwrite,
+        (false, Some(policy)) => policy,
+        (false, None) => gdy_project.overwrite_policy(),
+    };
+    let options = WriteOptions::new(cli.dry_run, policy);
+    let structure_formats = match &cli.formats {
+        Some(formats) => StructureFormats::new(
+            formats.clone(),
+            gdy_project.structure_formats().poscar_coordinates(),
+        ),
+        None => gdy_project.structure_formats().clone(),
+    };
+    let seed_exporter = || seed_exporter(&project_info, &gdy_project, &structure_formats);
+    let report = match &cli.command {
+        Command::GenBase(filter) => task_gen_base(
+            &project_info,
+            &gdy_project,
+            filter,
+            &seed_exporter()?,
+            &options,
+        )?,
+        Command::GenAds(filter) => task_gen_ads(
+            &project_info,
+            &gdy_project,
+            filter,
+            &seed_exporter()?,
+            &options,
+        )?,
+        Command::ExportSeeds(filter) => task_export_seeds(
+            &project_info,
+            &gdy_project,
+            filter,
+            &seed_exporter()?,
+            &options,
+        )?,
+        Command::WriteScripts { pattern } => {
+            task_write_scripts(&project_info, &gdy_project, pattern, &options)?;
+            FailureReport::default()
+        }
+        Command::Status => task_status(&project_info, &gdy_project, &options)?,
+        Command::Restart {
+            statuses,
+            continuation,
+        } => task_restart(
+            &project_info,
+            &gdy_project,
+            statuses,
+            *continuation,
+            &seed_exporter()?,
+            &options,
+        )?,
+        Command::Dos => task_dos(&project_info, &gdy_project, &structure_formats, &options)?,
+        Command::Trajectory { dirs } => {
+            task_trajectory(&project_info, &gdy_project, dirs, &options)?
+        }
+        Command::DBand => task_d_band(&project_info, &gdy_project, &options)?,
+        Command::Collect => task_collect(&project_info, &gdy_project, &options)?,
+    };
+    if report.is_empty() {
+        return Ok(ExitCode::SUCCESS);
+    }
+    println!("{}", report.table());
+    Ok(ExitCode::FAILURE)
 }
 
-fn task_gen_all(project_info: &ProjectInfo) -> Result<(), Box<dyn Error>> {
-    println!("Generate all base models");
-    gdy_tri_basic_models::editor::gdy_tri_editor::generate_all_base_models(
+fn seed_exporter(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    structure_formats: &StructureFormats,
+) -> Result<SeedExporter, Box<dyn Error>> {
+    seed_exporter_with(
+        project_info,
+        gdy_project,
+        gdy_project.geom_param_loc(),
+        structure_formats,
+    )
+}
+
+fn seed_exporter_with(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    param_template_loc: &str,
+    structure_formats: &StructureFormats,
+) -> Result<SeedExporter, Box<dyn Error>> {
+    SeedExporter::new(
+        project_info.element_table_loc(),
+        param_template_loc,
+        gdy_project.job_script(),
+        PotentialResolver::new(
+            project_info.potentials_loc(),
+            gdy_project.potential_bundling(),
+        ),
+        structure_formats.clone(),
+    )
+}
+
+fn task_gen_base(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    filter: &ElementFilter,
+    seed_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let metals = filter.metals(gdy_project)?;
+    println!("Generate base models of {} metals", metals.len());
+    generate_all_base_models(
         project_info.base_model_loc(),
         project_info.export_loc(),
+        &metals,
+        gdy_project,
+        seed_exporter,
+        options,
+    )
+}
+
+/// The `.msi` models with one of `metals` on a substitution site.
+fn models_with_metals(
+    msi_paths: Vec<PathBuf>,
+    gdy_project: &GdyProject,
+    metals: &[&Element],
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut kept: Vec<PathBuf> = vec![];
+    for path in msi_paths {
+        let lattice = read_lattice(&path.to_string_lossy())?;
+        if model_has_element(&lattice, gdy_project.substitution_sites(), metals)? {
+            kept.push(path);
+        }
+    }
+    Ok(kept)
+}
+
+fn task_gen_ads(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    filter: &ElementFilter,
+    seed_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let metals = filter.metals(gdy_project)?;
+    let base_models = models_with_metals(
+        find_base_models(project_info.export_loc())?,
+        gdy_project,
+        &metals,
+    )?;
+    println!("Place adsorbates on {} base models", base_models.len());
+    generate_all_ads_models(
+        &base_models,
+        project_info.export_loc(),
+        project_info.adsorbate_table_loc(),
+        gdy_project,
+        seed_exporter,
+        options,
+    )
+}
+
+fn task_export_seeds(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    filter: &ElementFilter,
+    seed_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let metals = filter.metals(gdy_project)?;
+    let pattern = format!("{}/**/*.msi", project_info.export_loc());
+    let msi_paths = models_with_metals(
+        glob(&pattern)?.filter_map(|path| path.ok()).collect(),
+        gdy_project,
+        &metals,
     )?;
+    let mut symbols: Vec<&str> = metals.iter().map(|elm| elm.symbol).collect();
+    let base_lattice = read_lattice(project_info.base_model_loc())?;
+    species_list(&base_lattice).into_iter().for_each(|elm| {
+        if !symbols.contains(&elm) {
+            symbols.push(elm)
+        }
+    });
+    seed_exporter.check_potentials(&symbols)?;
+    let mut statuses: Vec<WriteStatus> = vec![];
+    let mut report = FailureReport::default();
+    msi_paths
+        .iter()
+        .for_each(|path| match seed_exporter.export_existing(path, options) {
+            Ok(status) => statuses.push(status),
+            Err(e) => report.push(ModelFailure::new(
+                &path.file_stem().unwrap_or_default().to_string_lossy(),
+                e,
+            )),
+        });
+    println!("{}", status_summary("seeds", &statuses, options));
+    Ok(report)
+}
+
+fn task_write_scripts(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    pattern: &str,
+    options: &WriteOptions,
+) -> Result<(), Box<dyn Error>> {
+    let settings = gdy_project
+        .job_script()
+        .ok_or("No job_script entry in the project file")?;
+    let statuses: Vec<WriteStatus> = JobScriptWriter::new(settings)?
+        .write_scripts_in(project_info.export_loc(), pattern, options)?
+        .into_iter()
+        .map(|(_, status)| status)
+        .collect();
+    println!("{}", status_summary("job scripts", &statuses, options));
     Ok(())
 }
+
+fn task_status(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let pattern = gdy_project.naming().seed_dir("*");
+    let (statuses, report) = scan_seeds(project_info.export_loc(), &pattern)?;
+    println!("{}", count_table(&statuses));
+    if !options.dry_run() {
+        let output = Path::new(project_info.export_loc()).join("status.csv");
+        write_status_table(&output, &statuses)?;
+        println!(
+            "Status of {} seeds written to {}",
+            statuses.len(),
+            output.display()
+        );
+    }
+    Ok(report)
+}
+
+fn task_restart(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    statuses: &[JobStatus],
+    continuation: bool,
+    seed_exporter: &SeedExporter,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let settings = gdy_project.restart();
+    let settings = RestartSettings::new(
+        settings.continuation() || continuation,
+        settings.param_overrides().clone(),
+    );
+    let pattern = gdy_project.naming().seed_dir("*");
+    let root = Path::new(project_info.export_loc());
+    let mut report = FailureReport::default();
+    let mut restarted = 0;
+    for seed_dir in seed_dirs_in(project_info.export_loc(), &pattern)? {
+        let attempt = latest_attempt(&seed_dir);
+        let result = seed_status(root, &attempt).and_then(|seed| {
+            if !statuses.contains(&seed.status()) {
+                return Ok(());
+            }
+            if options.dry_run() {
+                println!("{} ({})", attempt.display(), seed.status());
+                restarted += 1;
+                return Ok(());
+            }
+            let restart_dir = write_restart(&attempt, seed_exporter, &settings)?;
+            restarted += 1;
+            println!(
+                "{} ({}) -> {}",
+                attempt.display(),
+                seed.status(),
+                restart_dir.display()
+            );
+            Ok(())
+        });
+        if let Err(e) = result {
+            report.push(ModelFailure::new(
+                &attempt.file_name().unwrap_or_default().to_string_lossy(),
+                e,
+            ));
+        }
+    }
+    println!(
+        "{} {} seeds, {} failed",
+        if options.dry_run() {
+            "Dry run: would restart"
+        } else {
+            "Restarted"
+        },
+        restarted,
+        report.failures().len()
+    );
+    Ok(report)
+}
+
+fn task_dos(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    structure_formats: &StructureFormats,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let dos_exporter = seed_exporter_with(
+        project_info,
+        gdy_project,
+        gdy_project.dos_param_loc(),
+        structure_formats,
+    )?;
+    let pattern = gdy_project.naming().seed_dir("*");
+    let root = Path::new(project_info.export_loc());
+    let mut report = FailureReport::default();
+    let mut statuses: Vec<WriteStatus> = vec![];
+    for seed_dir in seed_dirs_in(project_info.export_loc(), &pattern)? {
+        let attempt = latest_attempt(&seed_dir);
+        let result = seed_status(root, &attempt).and_then(|seed| {
+            if seed.status() == JobStatus::Converged {
+                let (_, status) = write_dos_seed(&attempt, &dos_exporter, options)?;
+                statuses.push(status);
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            report.push(ModelFailure::new(
+                &attempt.file_name().unwrap_or_default().to_string_lossy(),
+                e,
+            ));
+        }
+    }
+    println!("{}", status_summary("DOS seeds", &statuses, options));
+    Ok(report)
+}
+
+fn task_trajectory(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    dirs: &[PathBuf],
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let dirs = if dirs.is_empty() {
+        let pattern = gdy_project.naming().seed_dir("*");
+        let mut dirs = seed_dirs_in(project_info.export_loc(), &pattern)?;
+        dirs.iter_mut()
+            .for_each(|seed_dir| *seed_dir = latest_attempt(seed_dir));
+        dirs
+    } else {
+        dirs.to_vec()
+    };
+    let mut report = FailureReport::default();
+    let mut written = 0;
+    for seed_dir in dirs.iter() {
+        let result = seed_name_in(seed_dir).and_then(|seed| {
+            if !seed_dir.join(format!("{}.geom", seed)).exists() {
+                return Ok(());
+            }
+            written += 1;
+            if options.dry_run() {
+                println!("{}", seed_dir.display());
+                return Ok(());
+            }
+            let (xyz_path, table_path, frames) = write_trajectory(seed_dir)?;
+            println!(
+                "{}: {} steps -> {}, {}",
+                seed_dir.display(),
+                frames,
+                xyz_path.display(),
+                table_path.display()
+            );
+            Ok(())
+        });
+        if let Err(e) = result {
+            report.push(ModelFailure::new(
+                &seed_dir.file_name().unwrap_or_default().to_string_lossy(),
+                e,
+            ));
+        }
+    }
+    println!(
+        "{}{} trajectories",
+        if options.dry_run() { "Dry run: " } else { "" },
+        written
+    );
+    Ok(report)
+}
+
+fn task_d_band(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let settings = gdy_project.d_band();
+    let dirs = finished_dos_dirs(project_info.export_loc())?;
+    let mut rows: Vec<DBandRow> = vec![];
+    let mut report = FailureReport::default();
+    for dos_dir in dirs.iter() {
+        match dos_d_band_rows(dos_dir, gdy_project, gdy_project.collect(), settings) {
+            Ok(model_rows) => rows.extend(model_rows),
+            Err(e) => report.push(ModelFailure::new(
+                &dos_dir.file_name().unwrap_or_default().to_string_lossy(),
+                e,
+            )),
+        }
+    }
+    let output = Path::new(project_info.export_loc()).join(settings.output());
+    if !options.dry_run() {
+        fs::write(&output, d_band_csv_text(&rows, gdy_project.collect()))?;
+    }
+    println!(
+        "{}{} DOS seeds, {} atom/spin rows to {}",
+        if options.dry_run() { "Dry run " } else { "" },
+        dirs.len() - report.failures().len(),
+        rows.len(),
+        output.display()
+    );
+    Ok(report)
+}
+
+fn task_collect(
+    project_info: &ProjectInfo,
+    gdy_project: &GdyProject,
+    options: &WriteOptions,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let settings = gdy_project.collect();
+    let base_models = find_base_models(project_info.export_loc())?;
+    let ads_table = load_ads_table(project_info.adsorbate_table_loc())?;
+    let (table, report) = collect_energies(
+        &base_models,
+        project_info.export_loc(),
+        &ads_table,
+        gdy_project,
+        settings,
+    );
+    let output = Path::new(project_info.export_loc()).join(settings.output());
+    let flags_output = settings
+        .flags_output()
+        .map(|flags| Path::new(project_info.export_loc()).join(flags));
+    if !options.dry_run() {
+        fs::write(&output, table.csv_text(settings))?;
+        if let Some(flags_output) = &flags_output {
+            fs::write(flags_output, table.flags_csv_text(settings))?;
+        }
+    }
+    println!(
+        "{}{} base models x {} adsorbate models to {}: {} energies, {} unconverged, {} missing",
+        if options.dry_run() { "Dry run " } else { "" },
+        table.rows().len(),
+        table.columns().len(),
+        output.display(),
+        table.count(|cell| matches!(cell, EnergyCell::Energy(_))),
+        table.count(|cell| *cell == EnergyCell::Unconverged),
+        table.count(|cell| *cell == EnergyCell::Missing)
+    );
+    if let Some(flags_output) = flags_output {
+        println!("Convergence flags to {}", flags_output.display());
+    }
+    Ok(report)
+}
@@ -0,0 +1,462 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, create_dir_all},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use castep_model_generator_backend::{atom::AtomArray, lattice::Lattice};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::project_config::SubstitutionSite;
+
+/// What happened to a model file during a generation run.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteStatus {
+    /// The file did not exist and has been written.
+    Created,
+    /// The file exists with the same content and has been left as is.
+    Unchanged,
+    /// The file exists with a different content and has been left as is.
+    Changed,
+    /// The file existed and has been replaced.
+    Overwritten,
+    /// The seed files existed with a different content, have been renamed to `*.bak{N}` and replaced.
+    BackedUp,
+    /// The model failed validation and has not been written.
+    Rejected,
+}
+
+impl WriteStatus {
+    /// Whether the model file is (or, in a dry run, would be) written in this run.
+    pub fn is_written(&self) -> bool {
+        matches!(
+            self,
+            WriteStatus::Created | WriteStatus::Overwritten | WriteStatus::BackedUp
+        )
+    }
+}
+
+impl Display for WriteStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            WriteStatus::Created => "created",
+            WriteStatus::Unchanged => "unchanged",
+            WriteStatus::Changed => "changed",
+            WriteStatus::Overwritten => "overwritten",
+            WriteStatus::BackedUp => "backedup",
+            WriteStatus::Rejected => "rejected",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// What to do with a model whose files already exist.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverwritePolicy {
+    /// Leave existing files as they are.
+    #[default]
+    Skip,
+    /// Replace existing files, whatever their content.
+    Overwrite,
+    /// Replace existing files whose content differs.
+    OverwriteIfDiffers,
+    /// Rename the seed files to `*.bak{N}` when the content differs, then write.
+    Backup,
+}
+
+impl Display for OverwritePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            OverwritePolicy::Skip => "skip",
+            OverwritePolicy::Overwrite => "overwrite",
+            OverwritePolicy::OverwriteIfDiffers => "overwrite-if-differs",
+            OverwritePolicy::Backup => "backup",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for OverwritePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OverwritePolicy::Skip),
+            "overwrite" => Ok(OverwritePolicy::Overwrite),
+            "overwrite-if-differs" => Ok(OverwritePolicy::OverwriteIfDiffers),
+            "backup" => Ok(OverwritePolicy::Backup),
+            _ => Err(format!(
+                "Unknown overwrite policy {}, expected skip, overwrite, overwrite-if-differs or backup",
+                s
+            )),
+        }
+    }
+}
+
+/// Switches of a generation run, set from the command line.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Report the statuses without writing anything.
+    dry_run: bool,
+    policy: OverwritePolicy,
+}
+
+impl WriteOptions {
+    pub fn new(dry_run: bool, policy: OverwritePolicy) -> Self {
+        Self { dry_run, policy }
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn policy(&self) -> OverwritePolicy {
+        self.policy
+    }
+}
+
+/// One generated model in the manifest.
+#[derive(Serialize, Debug, Clone)]
+pub struct ManifestEntry {
+    model: String,
+    /// Elements on the substitution sites, in site order.
+    elements: Vec<String>,
+    path: String,
+    /// SHA-256 of the generated `.msi` text.
+    hash: String,
+    status: WriteStatus,
+    /// Failed geometric checks, see `validation::validate_lattice`.
+    issues: Vec<String>,
+}
+
+impl ManifestEntry {
+    pub fn new(
+        model: &str,
+        elements: Vec<String>,
+        path: &Path,
+        hash: String,
+        status: WriteStatus,
+        issues: Vec<String>,
+    ) -> Self {
+        Self {
+            model: model.to_string(),
+            elements,
+            path: path.display().to_string(),
+            hash,
+            status,
+            issues,
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        self.model.as_ref()
+    }
+
+    pub fn elements(&self) -> &[String] {
+        self.elements.as_ref()
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_ref()
+    }
+
+    pub fn hash(&self) -> &str {
+        self.hash.as_ref()
+    }
+
+    pub fn status(&self) -> WriteStatus {
+        self.status
+    }
+
+    pub fn issues(&self) -> &[String] {
+        self.issues.as_ref()
+    }
+}
+
+pub fn content_hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Elements currently on the substitution sites of `lattice`.
+pub fn site_elements(lattice: &Lattice, sites: &[SubstitutionSite]) -> Vec<String> {
+    sites
+        .iter()
+        .filter_map(|site| lattice.atoms_vec().get_atom_by_id(site.atom_id()))
+        .map(|atom| atom.element_name().to_string())
+        .collect()
+}
+
+/// Status of writing `text` to `filepath` under `policy`, without touching the disk.
+pub fn planned_status(
+    filepath: &Path,
+    text: &str,
+    policy: OverwritePolicy,
+) -> Result<WriteStatus, Box<dyn Error>> {
+    planned_seed_status(&[(filepath.to_path_buf(), text.to_string())], policy)
+}
+
+/**
+Status of writing the `files` (path, text) of one seed under `policy`, without touching the disk.
+The files are compared together by content hash: the seed is unchanged only when every file
+exists with the same content, so a new `.param` template or job script counts as a change.
+`Overwrite` rewrites an existing seed whatever its content.
+*/
+pub fn planned_seed_status(
+    files: &[(PathBuf, String)],
+    policy: OverwritePolicy,
+) -> Result<WriteStatus, Box<dyn Error>> {
+    if !files.iter().any(|(path, _)| path.exists()) {
+        return Ok(WriteStatus::Created);
+    }
+    if policy == OverwritePolicy::Overwrite {
+        return Ok(WriteStatus::Overwritten);
+    }
+    let mut same = true;
+    for (path, text) in files {
+        same &= path.exists() && content_hash(&fs::read_to_string(path)?) == content_hash(text);
+    }
+    Ok(match (same, policy) {
+        (true, _) => WriteStatus::Unchanged,
+        (false, OverwritePolicy::Backup) => WriteStatus::BackedUp,
+        (false, OverwritePolicy::Skip) => WriteStatus::Changed,
+        (false, _) => WriteStatus::Overwritten,
+    })
+}
+
+/**
+Rename the files `{seed_dir}/{seed_name}.*` to `{file}.bak{N}`, with the first free `N`,
+so CASTEP outputs of the previous model are not mixed with the new ones.
+# Arguments:
+- keep_extensions: `&[&str]` - extensions left in place, e.g. `["msi"]` when only the seed is rewritten
+# Returns:
+- Paths of the backups.
+*/
+pub fn backup_seed_files(
+    seed_dir: &Path,
+    seed_name: &str,
+    keep_extensions: &[&str],
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut backups: Vec<PathBuf> = vec![];
+    for entry in fs::read_dir(seed_dir)? {
+        let path = entry?.path();
+        let is_seed_file = path.is_file()
+            && path.file_stem().is_some_and(|stem| stem == seed_name)
+            && path.extension().is_some_and(|ext| {
+                let ext = ext.to_string_lossy();
+                !ext.starts_with("bak") && !keep_extensions.contains(&ext.as_ref())
+            });
+        if !is_seed_file {
+            continue;
+        }
+        let backup = (1..)
+            .map(|n| PathBuf::from(format!("{}.bak{}", path.display(), n)))
+            .find(|backup| !backup.exists())
+            .unwrap();
+        fs::rename(&path, &backup)?;
+        backups.push(backup);
+    }
+    Ok(backups)
+}
+
+/**
+Write `text` to `filepath` under the overwrite policy of `options`, creating the parent directory.
+With the backup policy the seed files of the same name are renamed first.
+With `dry_run` nothing is written.
+# Returns:
+- (status, hash of `text`)
+*/
+pub fn write_with_policy(
+    filepath: &Path,
+    text: &str,
+    options: &WriteOptions,
+) -> Result<(WriteStatus, String), Box<dyn Error>> {
+    let status = planned_status(filepath, text, options.policy())?;
+    write_planned(filepath, text, status, options)?;
+    Ok((status, content_hash(text)))
+}
+
+/// Write `text` to `filepath` when `status` is written and this is not a dry run,
+/// renaming the seed files of the same name first for `BackedUp`.
+fn write_planned(
+    filepath: &Path,
+    text: &str,
+    status: WriteStatus,
+    options: &WriteOptions,
+) -> Result<(), Box<dyn Error>> {
+    if status.is_written() && !options.dry_run() {
+        let parent = filepath.parent().unwrap_or(Path::new("."));
+        create_dir_all(parent)?;
+        if status == WriteStatus::BackedUp {
+            let stem = filepath.file_stem().unwrap_or_default().to_string_lossy();
+            backup_seed_files(parent, &stem, &[])?;
+        }
+        fs::write(filepath, text)?;
+    }
+    Ok(())
+}
+
+/// Write `{dir}/{stem}.json` and `{dir}/{stem}.csv` listing `entries`.
+pub fn write_manifest(
+    dir: &Path,
+    stem: &str,
+    entries: &[ManifestEntry],
+) -> Result<(), Box<dyn Error>> {
+    create_dir_all(dir)?;
+    fs::write(
+        dir.join(format!("{}.json", stem)),
+        serde_json::to_string_pretty(entries)?,
+    )?;
+    let mut lines = vec!["model,elements,path,hash,status,issues".to_string()];
+    entries.iter().for_each(|entry| {
+        let fields = [
+            entry.model().to_string(),
+            entry.elements().join("-"),
+            entry.path().to_string(),
+            entry.hash().to_string(),
+            entry.status().to_string(),
+            entry.issues().join("; "),
+        ];
+        lines.push(
+            fields
+                .iter()
+                .map(|field| csv_field(field))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+    });
+    fs::write(dir.join(format!("{}.csv", stem)), lines.join("\n"))?;
+    Ok(())
+}
+
+/// `field` as a CSV field, quoted when it contains a comma, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// One-line count of the entries per status, under the overwrite policy of the run.
+pub fn summary(entries: &[ManifestEntry], options: &WriteOptions) -> String {
+    let count = |status: WriteStatus| entries.iter().filter(|e| e.status() == status).count();
+    format!(
+        "{}[{}] {} models: {} created, {} unchanged, {} changed, {} overwritten, {} backed up, {} rejected; {} with validation issues",
+        if options.dry_run() { "Dry run " } else { "" },
+        options.policy(),
+        entries.len(),
+        count(WriteStatus::Created),
+        count(WriteStatus::Unchanged),
+        count(WriteStatus::Changed),
+        count(WriteStatus::Overwritten),
+        count(WriteStatus::BackedUp),
+        count(WriteStatus::Rejected),
+        entries.iter().filter(|e| !e.issues().is_empty()).count()
+    )
+}
+
+/// Policy and count of each status of the files of one kind, e.g. `job scripts`.
+pub fn status_summary(kind: &str, statuses: &[WriteStatus], options: &WriteOptions) -> String {
+    let count = |status: WriteStatus| statuses.iter().filter(|s| **s == status).count();
+    format!(
+        "{}[{}] {} {}: {} created, {} unchanged, {} changed, {} overwritten, {} backed up",
+        if options.dry_run() { "Dry run " } else { "" },
+        options.policy(),
+        statuses.len(),
+        kind,
+        count(WriteStatus::Created),
+        count(WriteStatus::Unchanged),
+        count(WriteStatus::Changed),
+        count(WriteStatus::Overwritten),
+        count(WriteStatus::BackedUp)
+    )
+}
+
+/// One line per model with validation issues.
+pub fn validation_report(entries: &[ManifestEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .filter(|entry| !entry.issues().is_empty())
+        .map(|entry| {
+            format!(
+                "{} ({}): {}",
+                entry.model(),
+                entry.status(),
+                entry.issues().join("; ")
+            )
+        })
+        .collect()
+}
+
+/**
+Write a validated model, unless it has issues and `skip_invalid` is set.
+The overwrite policy is decided on the model together with its `seed_files`
+(e.g. from `SeedExporter::seed_files`), which the caller writes when the status is written.
+# Returns:
+- (status, hash of `text`)
+*/
+pub fn write_validated(
+    filepath: &Path,
+    text: &str,
+    seed_files: &[(PathBuf, String)],
+    issues: &[String],
+    skip_invalid: bool,
+    options: &WriteOptions,
+) -> Result<(WriteStatus, String), Box<dyn Error>> {
+    if !issues.is_empty() && skip_invalid {
+        return Ok((WriteStatus::Rejected, content_hash(text)));
+    }
+    let mut files = vec![(filepath.to_path_buf(), text.to_string())];
+    files.extend_from_slice(seed_files);
+    let status = planned_seed_status(&files, options.policy())?;
+    write_planned(filepath, text, status, options)?;
+    Ok((status, content_hash(text)))
+}
+
+#[cfg(test)]
+#[test]
+fn test_content_hash() {
+    assert_eq!(
+        content_hash(""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_ne!(content_hash("GDY_Fe_Fe_Co"), content_hash("GDY_Fe_Fe_Ni"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_overwrite_policy() {
+    let dir = std::env::temp_dir().join("gdy_test_overwrite_policy");
+    let _ = fs::remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    let msi = dir.join("GDY_Fe_Fe_Co.msi");
+    fs::write(&msi, "old").unwrap();
+    fs::write(dir.join("GDY_Fe_Fe_Co.castep"), "old run").unwrap();
+    let policy = |policy: &str| WriteOptions::new(false, policy.parse().unwrap());
+    let (status, _) = write_with_policy(&msi, "old", &policy("overwrite-if-differs")).unwrap();
+    assert_eq!(status, WriteStatus::Unchanged);
+    let (status, _) = write_with_policy(&msi, "old", &policy("overwrite")).unwrap();
+    assert_eq!(status, WriteStatus::Overwritten);
+    let (status, _) = write_with_policy(&msi, "new", &policy("skip")).unwrap();
+    assert_eq!(status, WriteStatus::Changed);
+    let (status, _) = write_with_policy(&msi, "new", &policy("backup")).unwrap();
+    assert_eq!(status, WriteStatus::BackedUp);
+    assert_eq!(fs::read_to_string(&msi).unwrap(), "new");
+    assert_eq!(
+        fs::read_to_string(dir.join("GDY_Fe_Fe_Co.msi.bak1")).unwrap(),
+        "old"
+    );
+    assert!(dir.join("GDY_Fe_Fe_Co.castep.bak1").exists());
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(csv_field("GDY_Fe_Fe_Co"), "GDY_Fe_Fe_Co");
+    assert_eq!(csv_field("runs,2/GDY.msi"), "\"runs,2/GDY.msi\"");
+    assert_eq!(csv_field("a \"b\""), "\"a \"\"b\"\"\"");
+}
@@ -0,0 +1,142 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::element_table::ElementTable;
+
+/// How the pseudopotential files are put into each seed directory.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PotentialBundling {
+    /// Copy the files, so the seed directory is self-contained.
+    #[default]
+    Copy,
+    /// Symlink to the files under `potentials_loc`.
+    Symlink,
+    /// Leave the seed directory without pseudopotentials.
+    None,
+}
+
+/// Pseudopotentials that could not be resolved, one line per element.
+#[derive(Debug, Clone)]
+pub struct MissingPotentialsError(pub Vec<String>);
+
+impl Display for MissingPotentialsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} pseudopotential(s) missing:", self.0.len())?;
+        write!(f, "{}", self.0.join("\n"))
+    }
+}
+
+impl Error for MissingPotentialsError {}
+
+/// Locates the pseudopotential of each element under `potentials_loc`.
+#[derive(Debug, Clone)]
+pub struct PotentialResolver {
+    potentials_loc: PathBuf,
+    bundling: PotentialBundling,
+}
+
+impl PotentialResolver {
+    pub fn new(potentials_loc: &str, bundling: PotentialBundling) -> Self {
+        Self {
+            potentials_loc: PathBuf::from(potentials_loc),
+            bundling,
+        }
+    }
+
+    pub fn bundling(&self) -> PotentialBundling {
+        self.bundling
+    }
+
+    /**
+    Resolve the pseudopotential files of `symbols`.
+    All missing entries (unknown element or absent file) are reported at once.
+    # Returns:
+    - (symbol, path of the pseudopotential), in the order of `symbols`.
+    */
+    pub fn resolve(
+        &self,
+        element_table: &ElementTable,
+        symbols: &[&str],
+    ) -> Result<Vec<(String, PathBuf)>, MissingPotentialsError> {
+        let mut resolved: Vec<(String, PathBuf)> = vec![];
+        let mut missing: Vec<String> = vec![];
+        symbols
+            .iter()
+            .for_each(|&symbol| match element_table.get_element(symbol) {
+                Ok(info) => {
+                    let path = self.potentials_loc.join(info.pot());
+                    if path.is_file() {
+                        resolved.push((symbol.to_string(), path));
+                    } else {
+                        missing.push(format!("{}: {} not found", symbol, path.display()));
+                    }
+                }
+                Err(e) => missing.push(format!("{}: {}", symbol, e)),
+            });
+        if missing.is_empty() {
+            Ok(resolved)
+        } else {
+            Err(MissingPotentialsError(missing))
+        }
+    }
+
+    /// Copy or symlink the pseudopotentials of `symbols` into `seed_dir`.
+    pub fn bundle(
+        &self,
+        element_table: &ElementTable,
+        symbols: &[&str],
+        seed_dir: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.bundling == PotentialBundling::None {
+            return Ok(());
+        }
+        for (_, pot_path) in self.resolve(element_table, symbols)? {
+            let dest = seed_dir.join(pot_path.file_name().unwrap());
+            if dest.exists() {
+                continue;
+            }
+            match self.bundling {
+                PotentialBundling::Copy => {
+                    fs::copy(&pot_path, &dest)?;
+                }
+                PotentialBundling::Symlink => link_potential(&pot_path, &dest)?,
+                PotentialBundling::None => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn link_potential(pot_path: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
+    std::os::unix::fs::symlink(fs::canonicalize(pot_path)?, dest)?;
+    Ok(())
+}
+
+/// Symlinks need extra privileges on other platforms, so copy instead.
+#[cfg(not(unix))]
+fn link_potential(pot_path: &Path, dest: &Path) -> Result<(), Box<dyn Error>> {
+    fs::copy(pot_path, dest)?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_missing_potentials() {
+    use crate::element_table::load_element_table;
+
+    let element_table = load_element_table("./resources/element_table.yaml").unwrap();
+    let resolver = PotentialResolver::new("./resources/no_such_dir", PotentialBundling::Copy);
+    let missing = resolver
+        .resolve(&element_table, &["C", "H", "Xx"])
+        .unwrap_err();
+    assert_eq!(missing.0.len(), 3);
+    assert!(missing.0[2].starts_with("Xx"));
+}
@@ -0,0 +1,374 @@
+use std::{error::Error, fmt::Display, fs};
+
+/**
+Define Structs to deserialize the GDY-specific settings from `project.yaml`.
+The common entries (`base_model_loc`, `export_loc`, ...) are still read by
+`load_project_info` from the backend; unknown keys are ignored on both sides.
+*/
+use serde::Deserialize;
+
+use crate::{
+    adsorbate::PlacementSettings, collector::CollectSettings, combination::Constraint,
+    d_band::DBandSettings, element_selection::ElementSelection, job_script::JobScriptSettings,
+    manifest::OverwritePolicy, potentials::PotentialBundling, restart::RestartSettings,
+    structure_export::StructureFormats, symmetry::SymmetrySettings, validation::ValidationSettings,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct GdyProject {
+    substitution_sites: Vec<SubstitutionSite>,
+    #[serde(default)]
+    elements: ElementSelection,
+    #[serde(default)]
+    constraints: Vec<Constraint>,
+    #[serde(default)]
+    naming: NamingScheme,
+    #[serde(default = "default_geom_param_loc")]
+    geom_param_loc: String,
+    #[serde(default = "default_dos_param_loc")]
+    dos_param_loc: String,
+    #[serde(default)]
+    symmetry: SymmetrySettings,
+    coord_sites: Vec<CoordSite>,
+    coord_cases: Vec<CoordCase>,
+    #[serde(default = "default_ads_model_loc")]
+    ads_model_loc: String,
+    #[serde(default)]
+    placement: PlacementSettings,
+    job_script: Option<JobScriptSettings>,
+    #[serde(default)]
+    potential_bundling: PotentialBundling,
+    #[serde(default)]
+    structure_formats: StructureFormats,
+    #[serde(default)]
+    validation: ValidationSettings,
+    #[serde(default)]
+    overwrite_policy: OverwritePolicy,
+    #[serde(default)]
+    collect: CollectSettings,
+    #[serde(default)]
+    restart: RestartSettings,
+    #[serde(default)]
+    d_band: DBandSettings,
+}
+
+fn default_ads_model_loc() -> String {
+    "resources/ads_models".to_string()
+}
+
+fn default_geom_param_loc() -> String {
+    "resources/geom.param".to_string()
+}
+
+fn default_dos_param_loc() -> String {
+    "resources/dos.param".to_string()
+}
+
+impl GdyProject {
+    pub fn substitution_sites(&self) -> &[SubstitutionSite] {
+        self.substitution_sites.as_ref()
+    }
+
+    /// Metals to substitute; the four families when absent.
+    pub fn elements(&self) -> &ElementSelection {
+        &self.elements
+    }
+
+    /// Constraints on the metals assigned to the site groups of one model.
+    pub fn constraints(&self) -> &[Constraint] {
+        self.constraints.as_ref()
+    }
+
+    pub fn naming(&self) -> &NamingScheme {
+        &self.naming
+    }
+
+    /// `.param` template copied into every geometry optimization seed.
+    pub fn geom_param_loc(&self) -> &str {
+        self.geom_param_loc.as_ref()
+    }
+
+    /// `.param` template of the `_DOS` seeds following the converged optimizations.
+    pub fn dos_param_loc(&self) -> &str {
+        self.dos_param_loc.as_ref()
+    }
+    pub fn symmetry(&self) -> &SymmetrySettings {
+        &self.symmetry
+    }
+    pub fn coord_sites(&self) -> &[CoordSite] {
+        self.coord_sites.as_ref()
+    }
+
+    pub fn coord_cases(&self) -> &[CoordCase] {
+        self.coord_cases.as_ref()
+    }
+
+    /// Directory of the adsorbate models, as `{ads_model_loc}/{pathName}/{name}.msi`.
+    pub fn ads_model_loc(&self) -> &str {
+        self.ads_model_loc.as_ref()
+    }
+
+    pub fn placement(&self) -> &PlacementSettings {
+        &self.placement
+    }
+
+    pub fn job_script(&self) -> Option<&JobScriptSettings> {
+        self.job_script.as_ref()
+    }
+
+    /// How the pseudopotentials under `potentials_loc` are put into each seed directory.
+    pub fn potential_bundling(&self) -> PotentialBundling {
+        self.potential_bundling
+    }
+
+    /// Structure formats written next to every `.msi`, besides the CASTEP seed.
+    pub fn structure_formats(&self) -> &StructureFormats {
+        &self.structure_formats
+    }
+
+    /// Geometric checks run on every generated model before it is written.
+    pub fn validation(&self) -> &ValidationSettings {
+        &self.validation
+    }
+
+    /// Handling of existing model files, unless `--policy` or `--force` is given.
+    pub fn overwrite_policy(&self) -> OverwritePolicy {
+        self.overwrite_policy
+    }
+
+    /// Layout of the energy CSV written by `collect`.
+    pub fn collect(&self) -> &CollectSettings {
+        &self.collect
+    }
+
+    /// Continuation and `.param` changes of the restart seeds.
+    pub fn restart(&self) -> &RestartSettings {
+        &self.restart
+    }
+
+    /// Metal atoms and energy window of the d-band descriptors written by `d-band`.
+    pub fn d_band(&self) -> &DBandSettings {
+        &self.d_band
+    }
+
+    /// Group labels in the order of their first appearance in `substitution_sites`.
+    pub fn site_groups(&self) -> Vec<&str> {
+        site_groups(self.substitution_sites())
+    }
+}
+
+/// A metal site to be substituted, and the group ("A", "B", ...) sharing the same metal.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubstitutionSite {
+    name: String,
+    atom_id: u32,
+    group: String,
+}
+
+impl SubstitutionSite {
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn atom_id(&self) -> u32 {
+        self.atom_id
+    }
+
+    pub fn group(&self) -> &str {
+        self.group.as_ref()
+    }
+}
+
+/// Named adsorption site on the base model.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CoordSite {
+    name: String,
+    atom_id: u32,
+}
+
+impl CoordSite {
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn atom_id(&self) -> u32 {
+        self.atom_id
+    }
+}
+
+/// Coordination cases of one kind: pairs of site atom ids, the second being `null` for single sites.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CoordCase {
+    name: String,
+    cases: Vec<(u32, Option<u32>)>,
+}
+
+impl CoordCase {
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn cases(&self) -> &[(u32, Option<u32>)] {
+        self.cases.as_ref()
+    }
+}
+
+/**
+Templates of the generated model names and seed directories.
+# Placeholders:
+- `model_name`: `{elements}` - substituted elements in site order, joined by `_`;
+  `{A}`, `{B}`, ... - metal of the site group; `{M1}`, ... - metal at the named site.
+- `seed_dir`: `{name}` - the rendered model name.
+*/
+#[derive(Deserialize, Debug, Clone)]
+pub struct NamingScheme {
+    model_name: String,
+    seed_dir: String,
+}
+
+impl Default for NamingScheme {
+    fn default() -> Self {
+        Self {
+            model_name: "GDY_{elements}".to_string(),
+            seed_dir: "{name}_opt".to_string(),
+        }
+    }
+}
+
+impl NamingScheme {
+    /**
+    Render the model name for one assignment of metals.
+    # Arguments:
+    - sites: `&[SubstitutionSite]` - substituted sites
+    - groups: `&[&str]` - group labels, aligned with `group_symbols`
+    - group_symbols: `&[&str]` - element symbol used for each group
+    # Returns:
+    - `UnknownSiteGroupError` when a site has a group without symbol.
+    */
+    pub fn model_name(
+        &self,
+        sites: &[SubstitutionSite],
+        groups: &[&str],
+        group_symbols: &[&str],
+    ) -> Result<String, UnknownSiteGroupError> {
+        let elements: Vec<&str> = sites
+            .iter()
+            .map(|site| {
+                groups
+                    .iter()
+                    .position(|&g| g == site.group())
+                    .and_then(|idx| group_symbols.get(idx).copied())
+                    .ok_or_else(|| UnknownSiteGroupError::new(site))
+            })
+            .collect::<Result<Vec<&str>, UnknownSiteGroupError>>()?;
+        let mut name = self.model_name.replace("{elements}", &elements.join("_"));
+        groups
+            .iter()
+            .zip(group_symbols)
+            .for_each(|(group, symbol)| {
+                name = name.replace(&format!("{{{}}}", group), symbol);
+            });
+        sites
+            .iter()
+            .zip(elements.iter())
+            .for_each(|(site, symbol)| {
+                name = name.replace(&format!("{{{}}}", site.name()), symbol);
+            });
+        Ok(name)
+    }
+    pub fn seed_dir(&self, model_name: &str) -> String {
+        self.seed_dir.replace("{name}", model_name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoSubstitutionSiteError;
+
+impl Display for NoSubstitutionSiteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No substitution_sites declared in project file")
+    }
+}
+
+impl Error for NoSubstitutionSiteError {}
+
+/// A substitution site whose group is not among the groups given a metal.
+#[derive(Debug, Clone)]
+pub struct UnknownSiteGroupError {
+    site: String,
+    group: String,
+}
+
+impl UnknownSiteGroupError {
+    pub fn new(site: &SubstitutionSite) -> Self {
+        Self {
+            site: site.name().to_string(),
+            group: site.group().to_string(),
+        }
+    }
+}
+
+impl Display for UnknownSiteGroupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Site {} has the group {}, which has no metal assigned",
+            self.site, self.group
+        )
+    }
+}
+
+impl Error for UnknownSiteGroupError {}
+
+/// Group labels in the order of their first appearance.
+pub fn site_groups(sites: &[SubstitutionSite]) -> Vec<&str> {
+    let mut groups: Vec<&str> = vec![];
+    sites.iter().for_each(|site| {
+        if !groups.contains(&site.group()) {
+            groups.push(site.group());
+        }
+    });
+    groups
+}
+
+pub fn load_gdy_project(filename: &str) -> Result<GdyProject, Box<dyn Error>> {
+    let text = fs::read_to_string(filename)?;
+    let project: GdyProject = serde_yaml::from_str(&text)?;
+    if project.substitution_sites().is_empty() {
+        return Err(Box::new(NoSubstitutionSiteError));
+    }
+    project.placement().validate()?;
+    Ok(project)
+}
+
+#[cfg(test)]
+#[test]
+fn test_naming_scheme() {
+    let sites: Vec<SubstitutionSite> = serde_yaml::from_str(
+        "[{name: M1, atom_id: 73, group: A}, {name: M2, atom_id: 74, group: A}, {name: M3, atom_id: 75, group: B}]",
+    )
+    .unwrap();
+    let groups = site_groups(&sites);
+    assert_eq!(groups, vec!["A", "B"]);
+    let naming = NamingScheme::default();
+    let name = naming.model_name(&sites, &groups, &["Fe", "Co"]).unwrap();
+    assert_eq!(name, "GDY_Fe_Fe_Co");
+    assert_eq!(naming.seed_dir(&name), "GDY_Fe_Fe_Co_opt");
+    let naming = NamingScheme {
+        model_name: "TAC_{A}2{B}_{M3}".to_string(),
+        seed_dir: "{name}".to_string(),
+    };
+    assert_eq!(
+        naming.model_name(&sites, &groups, &["Fe", "Co"]).unwrap(),
+        "TAC_Fe2Co_Co"
+    );
+    // A group without metal is an error naming the site, not a panic.
+    assert_eq!(
+        naming
+            .model_name(&sites, &groups, &["Fe"])
+            .unwrap_err()
+            .to_string(),
+        "Site M3 has the group B, which has no metal assigned"
+    );
+    assert!(naming.model_name(&sites, &["A"], &["Fe"]).is_err());
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    fs::{self, create_dir_all},
+    path::{Path, PathBuf},
+};
+
+use castep_model_generator_backend::lattice::Lattice;
+use serde::Deserialize;
+
+use crate::{
+    castep_output::read_castep, geom_output::read_geom, job_script::seed_name_in,
+    seed_export::set_param_value, seed_export::SeedExporter,
+};
+
+/// The `restart` entry of `project.yaml`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RestartSettings {
+    /// Set `continuation : default` and copy the `.check` of the stopped run.
+    continuation: bool,
+    /// `.param` entries replaced in the restart, e.g. `mix_charge_amp: 0.2`.
+    param_overrides: BTreeMap<String, serde_yaml::Value>,
+}
+
+impl RestartSettings {
+    pub fn new(continuation: bool, param_overrides: BTreeMap<String, serde_yaml::Value>) -> Self {
+        Self {
+            continuation,
+            param_overrides,
+        }
+    }
+
+    pub fn continuation(&self) -> bool {
+        self.continuation
+    }
+
+    pub fn param_overrides(&self) -> &BTreeMap<String, serde_yaml::Value> {
+        &self.param_overrides
+    }
+
+    /// `param_text` with the continuation and the overrides applied.
+    pub fn restart_param(&self, param_text: &str) -> String {
+        let mut text = param_text.to_string();
+        if self.continuation {
+            text = set_param_value(&text, "continuation", "default");
+        }
+        self.param_overrides.iter().for_each(|(key, value)| {
+            let value = match value {
+                serde_yaml::Value::String(value) => value.clone(),
+                value => serde_yaml::to_string(value)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            };
+            text = set_param_value(&text, key, &value);
+        });
+        text
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoGeometryError(pub PathBuf);
+
+impl Display for NoGeometryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No geometry in the .geom or .castep of {}",
+            self.0.display()
+        )
+    }
+}
+
+impl Error for NoGeometryError {}
+
+/**
+Last geometry of the seed `seed_name` in `seed_dir`: the last frame of `{seed_name}.geom`,
+else the last `Cell Contents` of `{seed_name}.castep`.
+*/
+pub fn last_geometry(seed_dir: &Path, seed_name: &str) -> Result<Lattice, Box<dyn Error>> {
+    let geom_path = seed_dir.join(format!("{}.geom", seed_name));
+    if geom_path.exists() {
+        if let Some(frame) = read_geom(&geom_path)?.last() {
+            return frame.to_lattice(seed_name);
+        }
+    }
+    let castep_path = seed_dir.join(format!("{}.castep", seed_name));
+    if castep_path.exists() {
+        if let Some(lattice) = read_castep(&castep_path)?.final_lattice(seed_name)? {
+            return Ok(lattice);
+        }
+    }
+    Err(Box::new(NoGeometryError(seed_dir.to_path_buf())))
+}
+
+/// Directory name without its `_restart_N` suffix.
+fn attempt_base_name(dir_name: &str) -> &str {
+    match dir_name.rsplit_once("_restart_") {
+        Some((base, n)) if n.parse::<u32>().is_ok() => base,
+        _ => dir_name,
+    }
+}
+
+/// `_restart_N` directories next to `seed_dir`, as (N, path) sorted by N.
+fn restart_dirs(seed_dir: &Path) -> Vec<(u32, PathBuf)> {
+    let dir_name = seed_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let prefix = format!("{}_restart_", attempt_base_name(&dir_name));
+    let mut dirs: Vec<(u32, PathBuf)> = seed_dir
+        .parent()
+        .and_then(|parent| fs::read_dir(parent).ok())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let n = name.strip_prefix(&prefix)?.parse::<u32>().ok()?;
+                    Some((n, entry.path()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    dirs.sort();
+    dirs
+}
+
+/// The most recent attempt of a seed: its last `_restart_N` directory, or `seed_dir` itself.
+pub fn latest_attempt(seed_dir: &Path) -> PathBuf {
+    restart_dirs(seed_dir)
+        .pop()
+        .map(|(_, dir)| dir)
+        .unwrap_or_else(|| seed_dir.to_path_buf())
+}
+
+/// `{seed_dir}_restart_{N}` with the next free N, the `_restart_` suffix of `seed_dir` excluded.
+pub fn next_restart_dir(seed_dir: &Path) -> PathBuf {
+    let dir_name = seed_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let n = restart_dirs(seed_dir)
+        .last()
+        .map(|(n, _)| n + 1)
+        .unwrap_or(1);
+    seed_dir.with_file_name(format!("{}_restart_{}", attempt_base_name(&dir_name), n))
+}
+
+/**
+Write a restart of the stopped run in `seed_dir` into the next `_restart_N` directory:
+the `.cell` from the last geometry, the `.param` of the run with the settings applied,
+the pseudopotentials and the job script of `seed_exporter`, and the `.check` for a continuation.
+# Returns:
+- The restart directory.
+*/
+pub fn write_restart(
+    seed_dir: &Path,
+    seed_exporter: &SeedExporter,
+    settings: &RestartSettings,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let seed_name = seed_name_in(seed_dir)?;
+    let lattice = last_geometry(seed_dir, &seed_name)?;
+    let check_path = seed_dir.join(format!("{}.check", seed_name));
+    if settings.continuation() && !check_path.exists() {
+        return Err(format!("No {} to continue from", check_path.display()).into());
+    }
+    let restart_dir = next_restart_dir(seed_dir);
+    create_dir_all(&restart_dir)?;
+    let (_, param_path) = seed_exporter.export_seed(&lattice, &restart_dir)?;
+    let param_text = fs::read_to_string(seed_dir.join(format!("{}.param", seed_name)))?;
+    fs::write(&param_path, settings.restart_param(&param_text))?;
+    if settings.continuation() {
+        fs::copy(
+            &check_path,
+            restart_dir.join(format!("{}.check", seed_name)),
+        )?;
+    }
+    Ok(restart_dir)
+}
+
+#[cfg(test)]
+#[test]
+fn test_restart_settings() {
+    let settings: RestartSettings = serde_yaml::from_str(
+        "{continuation: true, param_overrides: {mix_charge_amp: 0.2, max_scf_cycles: 200, metals_method: edft}}",
+    )
+    .unwrap();
+    let template = fs::read_to_string("./resources/geom.param").unwrap();
+    let text = settings.restart_param(&template);
+    assert!(text.contains("mix_charge_amp :      0.2\n"));
+    assert!(text.contains("max_scf_cycles :      200\n"));
+    assert!(text.contains("metals_method :     edft\n"));
+    assert!(text.ends_with("continuation : default\n"));
+    assert_eq!(
+        attempt_base_name("GDY_Fe_Fe_Co_opt_restart_2"),
+        "GDY_Fe_Fe_Co_opt"
+    );
+    assert_eq!(attempt_base_name("GDY_Fe_Fe_Co_opt"), "GDY_Fe_Fe_Co_opt");
+    assert_eq!(
+        next_restart_dir(Path::new("/nonexistent/GDY_Fe_Fe_Co_opt")),
+        Path::new("/nonexistent/GDY_Fe_Fe_Co_opt_restart_1")
+    );
+}
@@ -0,0 +1,510 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use castep_model_generator_backend::{lattice::Lattice, parser::msi_parser::parse_lattice};
+use nalgebra::{Matrix3, Point3};
+
+use crate::{
+    element_table::{load_element_table, ElementTable},
+    job_script::{JobScriptSettings, JobScriptWriter},
+    manifest::{backup_seed_files, planned_seed_status, WriteOptions, WriteStatus},
+    potentials::{MissingPotentialsError, PotentialResolver},
+    structure_export::StructureFormats,
+};
+
+/// Lattice without `A3/B3/C3` vectors, which cannot be written as a CASTEP cell.
+#[derive(Debug, Clone)]
+pub struct NoLatticeVectorsError(pub String);
+
+impl Display for NoLatticeVectorsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Lattice {} has no lattice vectors", self.0)
+    }
+}
+
+impl Error for NoLatticeVectorsError {}
+
+/// Lattice vectors as columns of a matrix, in Angstrom.
+pub fn lattice_matrix(lattice: &Lattice) -> Result<Matrix3<f64>, NoLatticeVectorsError> {
+    lattice
+        .lattice_vectors()
+        .map(|vectors| *vectors.vectors())
+        .ok_or_else(|| NoLatticeVectorsError(lattice.lattice_name().to_string()))
+}
+
+/// Fractional coordinates of every atom, in the order of `atoms_vec`.
+pub fn fractional_coordinates(lattice: &Lattice) -> Result<Vec<Point3<f64>>, Box<dyn Error>> {
+    let cell = lattice_matrix(lattice)?;
+    let to_frac = cell
+        .try_inverse()
+        .ok_or_else(|| NoLatticeVectorsError(lattice.lattice_name().to_string()))?;
+    Ok(lattice
+        .atoms_vec()
+        .iter()
+        .map(|atom| Point3::from(to_frac * atom.xyz().coords))
+        .collect())
+}
+
+/// Element symbols in the order of their first appearance in the lattice.
+pub fn species_list(lattice: &Lattice) -> Vec<&str> {
+    let mut species: Vec<&str> = vec![];
+    lattice.atoms_vec().iter().for_each(|atom| {
+        if !species.contains(&atom.element_name()) {
+            species.push(atom.element_name());
+        }
+    });
+    species
+}
+
+/**
+Set `key : value` in a `.param` text, keeping the `key :` column layout of the file.
+The entry is appended when the key is absent. Keys are matched case-insensitively.
+*/
+pub fn set_param_value(param_text: &str, key: &str, value: &str) -> String {
+    let mut found = false;
+    let mut lines: Vec<String> = param_text
+        .lines()
+        .map(|line| match line.split_once(':') {
+            Some((k, _)) if k.trim().eq_ignore_ascii_case(key) => {
+                found = true;
+                format!("{} : {:>8}", k.trim_end(), value)
+            }
+            _ => line.to_string(),
+        })
+        .collect();
+    if !found {
+        lines.push(format!("{} : {}", key, value));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Writes CASTEP seed files (`.cell`, `.param`, pseudopotentials and the job script)
+/// of generated lattices, and their structure in the extra formats.
+pub struct SeedExporter {
+    element_table: ElementTable,
+    param_template: String,
+    job_script: Option<JobScriptWriter>,
+    potentials: PotentialResolver,
+    structure_formats: StructureFormats,
+}
+
+impl SeedExporter {
+    /**
+    # Arguments:
+    - element_table_loc: `&str` - path to `element_table.yaml`
+    - param_template_loc: `&str` - `.param` to copy for every seed, e.g. `resources/geom.param`
+    - job_script: `Option<&JobScriptSettings>` - job script written next to every seed, if any
+    - potentials: `PotentialResolver` - pseudopotentials bundled into every seed directory
+    - structure_formats: `StructureFormats` - POSCAR, extended XYZ or CIF written with every seed
+    */
+    pub fn new(
+        element_table_loc: &str,
+        param_template_loc: &str,
+        job_script: Option<&JobScriptSettings>,
+        potentials: PotentialResolver,
+        structure_formats: StructureFormats,
+    ) -> Result<Self, Box<dyn Error>> {
+        let element_table = load_element_table(element_table_loc)?;
+        let param_template = fs::read_to_string(param_template_loc)?;
+        let job_script = job_script.map(JobScriptWriter::new).transpose()?;
+        Ok(Self {
+            element_table,
+            param_template,
+            job_script,
+            potentials,
+            structure_formats,
+        })
+    }
+
+    pub fn job_script(&self) -> Option<&JobScriptWriter> {
+        self.job_script.as_ref()
+    }
+
+    pub fn element_table(&self) -> &ElementTable {
+        &self.element_table
+    }
+
+    pub fn potentials(&self) -> &PotentialResolver {
+        &self.potentials
+    }
+
+    pub fn structure_formats(&self) -> &StructureFormats {
+        &self.structure_formats
+    }
+
+    /// Fail with every missing pseudopotential of `symbols` before anything is generated.
+    pub fn check_potentials(&self, symbols: &[&str]) -> Result<(), MissingPotentialsError> {
+        self.potentials
+            .resolve(&self.element_table, symbols)
+            .map(|_| ())
+    }
+
+    pub fn cell_text(&self, lattice: &Lattice) -> Result<String, Box<dyn Error>> {
+        let cell = lattice_matrix(lattice)?;
+        let frac_coords = fractional_coordinates(lattice)?;
+        let species = species_list(lattice);
+        let mut lines: Vec<String> = vec![];
+        lines.push("%BLOCK LATTICE_CART".to_string());
+        cell.column_iter().for_each(|vec| {
+            lines.push(format!("{:24.15}{:24.15}{:24.15}", vec[0], vec[1], vec[2]))
+        });
+        lines.push("%ENDBLOCK LATTICE_CART\n".to_string());
+        lines.push("%BLOCK POSITIONS_FRAC".to_string());
+        for (atom, frac) in lattice.atoms_vec().iter().zip(frac_coords.iter()) {
+            let spin = self.element_table.get_element(atom.element_name())?.spin();
+            let spin_text = if spin > 0 {
+                format!(" SPIN={:14.10}", spin as f64)
+            } else {
+                String::new()
+            };
+            lines.push(format!(
+                "{:>4}{:20.15}{:20.15}{:20.15}{}",
+                atom.element_name(),
+                frac.x,
+                frac.y,
+                frac.z,
+                spin_text
+            ))
+        }
+        lines.push("%ENDBLOCK POSITIONS_FRAC\n".to_string());
+        lines.push("%BLOCK KPOINTS_LIST".to_string());
+        lines.push(format!(
+            "{:20.15}{:20.15}{:20.15}{:20.15}",
+            0.0, 0.0, 0.0, 1.0
+        ));
+        lines.push("%ENDBLOCK KPOINTS_LIST\n".to_string());
+        lines.push("FIX_ALL_CELL : true\n".to_string());
+        lines.push("FIX_COM : false".to_string());
+        lines.push("%BLOCK IONIC_CONSTRAINTS".to_string());
+        lines.push("%ENDBLOCK IONIC_CONSTRAINTS\n".to_string());
+        lines.push("%BLOCK EXTERNAL_EFIELD".to_string());
+        lines.push(format!("{:16.10}{:16.10}{:16.10}", 0.0, 0.0, 0.0));
+        lines.push("%ENDBLOCK EXTERNAL_EFIELD\n".to_string());
+        lines.push("%BLOCK SPECIES_MASS".to_string());
+        for elm in species.iter() {
+            let info = self.element_table.get_element(elm)?;
+            lines.push(format!("{:>8}{:18.10}", elm, info.mass()));
+        }
+        lines.push("%ENDBLOCK SPECIES_MASS\n".to_string());
+        lines.push("%BLOCK SPECIES_POT".to_string());
+        for elm in species.iter() {
+            let info = self.element_table.get_element(elm)?;
+            lines.push(format!("{:>8}  {}", elm, info.pot()));
+        }
+        lines.push("%ENDBLOCK SPECIES_POT\n".to_string());
+        lines.push("%BLOCK SPECIES_LCAO_STATES".to_string());
+        for elm in species.iter() {
+            let info = self.element_table.get_element(elm)?;
+            lines.push(format!("{:>8}{:10}", elm, info.lcao()));
+        }
+        lines.push("%ENDBLOCK SPECIES_LCAO_STATES\n".to_string());
+        Ok(lines.join("\n"))
+    }
+
+    /**
+    Sum of the initial spins of all atoms, from the element table.
+    The metal moments are assumed to start ferromagnetically aligned: every `SPIN=` of the
+    `.cell` is positive, so e.g. two Fe (4) and one Co (3) give 11. Antiferromagnetic guesses
+    need the `.cell` and the `spin` of the `.param` to be edited by hand.
+    */
+    pub fn total_spin(&self, lattice: &Lattice) -> Result<u32, Box<dyn Error>> {
+        lattice
+            .atoms_vec()
+            .iter()
+            .map(|atom| -> Result<u32, Box<dyn Error>> {
+                Ok(self.element_table.get_element(atom.element_name())?.spin())
+            })
+            .sum()
+    }
+
+    /**
+    The `.param` template with `spin` set to the total initial spin of the model.
+    `spin_fix` keeps the template value for magnetic models and is reset to 0
+    when all initial spins vanish, so unpolarized guesses are not pinned.
+    */
+    pub fn param_text(&self, lattice: &Lattice) -> Result<String, Box<dyn Error>> {
+        let total_spin = self.total_spin(lattice)?;
+        let text = set_param_value(&self.param_template, "spin", &total_spin.to_string());
+        if total_spin == 0 {
+            Ok(set_param_value(&text, "spin_fix", "0"))
+        } else {
+            Ok(text)
+        }
+    }
+
+    /**
+    Path and text of every seed file of `lattice` in `seed_dir`: `{lattice_name}.cell`,
+    `{lattice_name}.param`, the structure in the extra formats and the job script when configured.
+    The overwrite policy compares these together, so a change of the templates is picked up.
+    */
+    pub fn seed_files(
+        &self,
+        lattice: &Lattice,
+        seed_dir: &Path,
+    ) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>> {
+        let seed_name = lattice.lattice_name();
+        let mut files = vec![
+            (
+                seed_dir.join(format!("{}.cell", seed_name)),
+                self.cell_text(lattice)?,
+            ),
+            (
+                seed_dir.join(format!("{}.param", seed_name)),
+                self.param_text(lattice)?,
+            ),
+        ];
+        files.extend(self.structure_formats.texts(lattice, seed_dir)?);
+        if let Some(job_script) = &self.job_script {
+            files.push((
+                seed_dir.join(job_script.settings().script_name()),
+                job_script.render(seed_name),
+            ));
+        }
+        Ok(files)
+    }
+
+    /**
+    Write `files`, e.g. from `seed_files`, into `seed_dir` and bundle the pseudopotentials
+    of the species of `lattice`.
+    */
+    pub fn write_seed_files(
+        &self,
+        lattice: &Lattice,
+        seed_dir: &Path,
+        files: &[(PathBuf, String)],
+    ) -> Result<(), Box<dyn Error>> {
+        for (path, text) in files {
+            fs::write(path, text)?;
+        }
+        self.potentials
+            .bundle(&self.element_table, &species_list(lattice), seed_dir)
+    }
+
+    /**
+    Write `{lattice_name}.cell` and `{lattice_name}.param` into `seed_dir`,
+    bundle the pseudopotentials of its species, write the structure in the extra formats
+    and the job script when configured.
+    # Returns:
+    - Paths of the written `.cell` and `.param`.
+    */
+    pub fn export_seed(
+        &self,
+        lattice: &Lattice,
+        seed_dir: &Path,
+    ) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+        let seed_name = lattice.lattice_name();
+        self.write_seed_files(lattice, seed_dir, &self.seed_files(lattice, seed_dir)?)?;
+        Ok((
+            seed_dir.join(format!("{}.cell", seed_name)),
+            seed_dir.join(format!("{}.param", seed_name)),
+        ))
+    }
+
+    /**
+    (Re)export the seed of an existing `.msi` into its own directory.
+    The seed is named after the file stem; the overwrite policy of `options` is applied
+    to the seed files together (see `seed_files`). Backups leave the `.msi` in place.
+    */
+    pub fn export_existing(
+        &self,
+        msi_path: &Path,
+        options: &WriteOptions,
+    ) -> Result<WriteStatus, Box<dyn Error>> {
+        let msi_name = msi_path
+            .to_str()
+            .ok_or_else(|| format!("Non UTF-8 path {}", msi_path.display()))?;
+        let mut lattice = parse_lattice(msi_name)?;
+        let seed_name = msi_path
+            .file_stem()
+            .ok_or_else(|| format!("No file name in {}", msi_path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let seed_dir = msi_path
+            .parent()
+            .ok_or_else(|| format!("No parent directory of {}", msi_path.display()))?;
+        lattice.set_lattice_name(seed_name.clone());
+        let files = self.seed_files(&lattice, seed_dir)?;
+        let status = planned_seed_status(&files, options.policy())?;
+        if status.is_written() && !options.dry_run() {
+            if status == WriteStatus::BackedUp {
+                backup_seed_files(seed_dir, &seed_name, &["msi"])?;
+            }
+            self.write_seed_files(&lattice, seed_dir, &files)?;
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_set_param_value() {
+    let template = fs::read_to_string("./resources/geom.param").unwrap();
+    let text = set_param_value(&template, "spin", "9");
+    assert!(text.contains("spin :        9\n"));
+    assert!(text.contains("spin_fix :        6\n"));
+    assert!(text.contains("spin_polarized : true"));
+    let text = set_param_value(&text, "continuation", "default");
+    assert!(text.ends_with("continuation : default\n"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_cell_text() {
+    use crate::{potentials::PotentialBundling, structure_import::build_lattice};
+
+    let exporter = SeedExporter::new(
+        "./resources/element_table.yaml",
+        "./resources/geom.param",
+        None,
+        PotentialResolver::new("./resources/potentials", PotentialBundling::None),
+        StructureFormats::default(),
+    )
+    .unwrap();
+    let lattice = build_lattice(
+        "GDY_C",
+        Matrix3::new(10.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 20.0),
+        vec![
+            ("C".to_string(), Point3::new(0.0, 0.0, 10.0)),
+            ("C".to_string(), Point3::new(5.0, 2.5, 10.0)),
+        ],
+    )
+    .unwrap();
+    let text = exporter.cell_text(&lattice).unwrap();
+    let block = |name: &str| -> Vec<String> {
+        text.split(&format!("%BLOCK {}\n", name))
+            .nth(1)
+            .and_then(|rest| rest.split(&format!("%ENDBLOCK {}", name)).next())
+            .unwrap()
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .collect()
+    };
+    assert_eq!(
+        block("LATTICE_CART"),
+        [
+            "10.000000000000000 0.000000000000000 0.000000000000000",
+            "0.000000000000000 10.000000000000000 0.000000000000000",
+            "0.000000000000000 0.000000000000000 20.000000000000000"
+        ]
+    );
+    assert_eq!(
+        block("POSITIONS_FRAC"),
+        [
+            "C 0.000000000000000 0.000000000000000 0.500000000000000",
+            "C 0.500000000000000 0.250000000000000 0.500000000000000"
+        ]
+    );
+    assert_eq!(
+        block("KPOINTS_LIST"),
+        ["0.000000000000000 0.000000000000000 0.000000000000000 1.000000000000000"]
+    );
+    assert_eq!(block("SPECIES_POT"), ["C C_00PBE.usp"]);
+    assert_eq!(block("SPECIES_LCAO_STATES"), ["C 2"]);
+    assert_eq!(block("SPECIES_MASS"), ["C 12.0109996796"]);
+    let param = exporter.param_text(&lattice).unwrap();
+    assert!(param.contains("spin :        0\n"));
+    assert!(param.contains("spin_fix :        0\n"));
+
+    let mixed = build_lattice(
+        "GDY_Fe_Fe_Co",
+        Matrix3::new(10.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 20.0),
+        vec![
+            ("Fe".to_string(), Point3::new(0.0, 0.0, 10.0)),
+            ("Fe".to_string(), Point3::new(5.0, 0.0, 10.0)),
+            ("Co".to_string(), Point3::new(0.0, 5.0, 10.0)),
+            ("C".to_string(), Point3::new(5.0, 5.0, 10.0)),
+        ],
+    )
+    .unwrap();
+    let spins: Vec<String> = exporter
+        .cell_text(&mixed)
+        .unwrap()
+        .split("%BLOCK POSITIONS_FRAC\n")
+        .nth(1)
+        .and_then(|rest| rest.split("%ENDBLOCK POSITIONS_FRAC").next())
+        .unwrap()
+        .lines()
+        .map(|line| {
+            line.split_whitespace()
+                .skip(4)
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+        .collect();
+    assert_eq!(
+        spins,
+        [
+            "SPIN= 4.0000000000",
+            "SPIN= 4.0000000000",
+            "SPIN= 3.0000000000",
+            ""
+        ]
+    );
+    assert_eq!(exporter.total_spin(&mixed).unwrap(), 11);
+    let param = exporter.param_text(&mixed).unwrap();
+    assert!(param.contains("spin :       11\n"));
+    assert!(param.contains("spin_fix :        6\n"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_export_existing_policy() {
+    use crate::potentials::PotentialBundling;
+
+    let dir = std::env::temp_dir().join("gdy_test_export_existing");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let msi = dir.join("GDY_tri.msi");
+    fs::copy("./resources/GDY_tri.msi", &msi).unwrap();
+    let template = fs::read_to_string("./resources/geom.param").unwrap();
+    let edited_loc = dir.join("edited.param");
+    fs::write(
+        &edited_loc,
+        set_param_value(&template, "cut_off_energy", "600"),
+    )
+    .unwrap();
+    let exporter = |param_loc: &str| {
+        SeedExporter::new(
+            "./resources/element_table.yaml",
+            param_loc,
+            None,
+            PotentialResolver::new("./resources/potentials", PotentialBundling::None),
+            StructureFormats::default(),
+        )
+        .unwrap()
+    };
+    let (original, edited) = (
+        exporter("./resources/geom.param"),
+        exporter(&edited_loc.to_string_lossy()),
+    );
+    let policy = |policy: &str| WriteOptions::new(false, policy.parse().unwrap());
+    let status = |exporter: &SeedExporter, options: &WriteOptions| {
+        exporter.export_existing(&msi, options).unwrap()
+    };
+    assert_eq!(status(&original, &policy("skip")), WriteStatus::Created);
+    assert_eq!(
+        status(&original, &policy("overwrite-if-differs")),
+        WriteStatus::Unchanged
+    );
+    assert_eq!(
+        status(&original, &policy("overwrite")),
+        WriteStatus::Overwritten
+    );
+    // Only the `.param` template changed: the `.cell` is the same, the seed is not.
+    assert_eq!(status(&edited, &policy("skip")), WriteStatus::Changed);
+    assert_eq!(
+        status(&edited, &policy("overwrite-if-differs")),
+        WriteStatus::Overwritten
+    );
+    let param = fs::read_to_string(dir.join("GDY_tri.param")).unwrap();
+    assert!(param.contains("cut_off_energy :      600\n"));
+    assert_eq!(
+        status(&edited, &policy("overwrite-if-differs")),
+        WriteStatus::Unchanged
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,296 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use castep_model_generator_backend::{atom::Atom, lattice::Lattice};
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use crate::seed_export::{fractional_coordinates, lattice_matrix, species_list};
+
+/// Structure formats written next to the `.msi` of every model.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StructureFormat {
+    /// VASP POSCAR, written as `{name}.vasp`.
+    Poscar,
+    /// Extended XYZ with the lattice in the comment line, written as `{name}.xyz`.
+    Extxyz,
+    /// CIF in space group P1, written as `{name}.cif`.
+    Cif,
+}
+
+impl StructureFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StructureFormat::Poscar => "vasp",
+            StructureFormat::Extxyz => "xyz",
+            StructureFormat::Cif => "cif",
+        }
+    }
+}
+
+impl FromStr for StructureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "poscar" => Ok(StructureFormat::Poscar),
+            "extxyz" => Ok(StructureFormat::Extxyz),
+            "cif" => Ok(StructureFormat::Cif),
+            _ => Err(format!(
+                "Unknown structure format {}, expected poscar, extxyz or cif",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PoscarCoordinates {
+    #[default]
+    Direct,
+    Cartesian,
+}
+
+/// The `structure_formats` entry of `project.yaml`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct StructureFormats {
+    #[serde(default)]
+    formats: Vec<StructureFormat>,
+    #[serde(default)]
+    poscar_coordinates: PoscarCoordinates,
+}
+
+impl StructureFormats {
+    pub fn new(formats: Vec<StructureFormat>, poscar_coordinates: PoscarCoordinates) -> Self {
+        Self {
+            formats,
+            poscar_coordinates,
+        }
+    }
+
+    pub fn formats(&self) -> &[StructureFormat] {
+        self.formats.as_ref()
+    }
+
+    pub fn poscar_coordinates(&self) -> PoscarCoordinates {
+        self.poscar_coordinates
+    }
+
+    pub fn text(
+        &self,
+        lattice: &Lattice,
+        format: StructureFormat,
+    ) -> Result<String, Box<dyn Error>> {
+        match format {
+            StructureFormat::Poscar => poscar_text(lattice, self.poscar_coordinates),
+            StructureFormat::Extxyz => extxyz_text(lattice),
+            StructureFormat::Cif => cif_text(lattice),
+        }
+    }
+
+    /// Path `{dir}/{lattice_name}.{extension}` and text of every selected format.
+    pub fn texts(
+        &self,
+        lattice: &Lattice,
+        dir: &Path,
+    ) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>> {
+        self.formats
+            .iter()
+            .map(|&format| {
+                let path = dir.join(format!("{}.{}", lattice.lattice_name(), format.extension()));
+                Ok((path, self.text(lattice, format)?))
+            })
+            .collect()
+    }
+
+    /// Write `{lattice_name}.{extension}` of every selected format into `dir`.
+    pub fn write_all(&self, lattice: &Lattice, dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        self.texts(lattice, dir)?
+            .into_iter()
+            .map(|(path, text)| {
+                fs::write(&path, text)?;
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+/// Atoms grouped by element, in the order of `species_list`; atoms keep their order within a group.
+fn grouped_atoms(lattice: &Lattice) -> Vec<(&str, Vec<(usize, &Atom)>)> {
+    species_list(lattice)
+        .into_iter()
+        .map(|elm| {
+            let atoms: Vec<(usize, &Atom)> = lattice
+                .atoms_vec()
+                .iter()
+                .enumerate()
+                .filter(|(_, atom)| atom.element_name() == elm)
+                .collect();
+            (elm, atoms)
+        })
+        .collect()
+}
+
+/// VASP 5 POSCAR with the atoms grouped by element.
+pub fn poscar_text(
+    lattice: &Lattice,
+    coordinates: PoscarCoordinates,
+) -> Result<String, Box<dyn Error>> {
+    let cell = lattice_matrix(lattice)?;
+    let frac_coords = fractional_coordinates(lattice)?;
+    let groups = grouped_atoms(lattice);
+    let mut lines: Vec<String> = vec![lattice.lattice_name().to_string(), "1.0".to_string()];
+    cell.column_iter()
+        .for_each(|vec| lines.push(format!("{:22.15}{:22.15}{:22.15}", vec[0], vec[1], vec[2])));
+    lines.push(
+        groups
+            .iter()
+            .map(|(elm, _)| format!("{:>5}", elm))
+            .collect::<String>(),
+    );
+    lines.push(
+        groups
+            .iter()
+            .map(|(_, atoms)| format!("{:>5}", atoms.len()))
+            .collect::<String>(),
+    );
+    lines.push(
+        match coordinates {
+            PoscarCoordinates::Direct => "Direct",
+            PoscarCoordinates::Cartesian => "Cartesian",
+        }
+        .to_string(),
+    );
+    groups.iter().for_each(|(_, atoms)| {
+        atoms.iter().for_each(|(idx, atom)| {
+            let xyz = match coordinates {
+                PoscarCoordinates::Direct => frac_coords[*idx],
+                PoscarCoordinates::Cartesian => *atom.xyz(),
+            };
+            lines.push(format!("{:20.15}{:20.15}{:20.15}", xyz.x, xyz.y, xyz.z))
+        })
+    });
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Extended XYZ with the lattice vectors and periodicity in the comment line.
+pub fn extxyz_text(lattice: &Lattice) -> Result<String, Box<dyn Error>> {
+    let cell = lattice_matrix(lattice)?;
+    let vectors: Vec<String> = cell
+        .column_iter()
+        .flat_map(|vec| {
+            vec.iter()
+                .map(|x| format!("{:.10}", x))
+                .collect::<Vec<String>>()
+        })
+        .collect();
+    let mut lines: Vec<String> = vec![
+        lattice.atoms_vec().len().to_string(),
+        format!(
+            "Lattice=\"{}\" Properties=species:S:1:pos:R:3 pbc=\"T T T\" name={}",
+            vectors.join(" "),
+            lattice.lattice_name()
+        ),
+    ];
+    lattice.atoms_vec().iter().for_each(|atom| {
+        let xyz = atom.xyz();
+        lines.push(format!(
+            "{:<4}{:20.15}{:20.15}{:20.15}",
+            atom.element_name(),
+            xyz.x,
+            xyz.y,
+            xyz.z
+        ))
+    });
+    Ok(lines.join("\n") + "\n")
+}
+
+/// CIF in P1 with one site per atom, labelled `{element}{atom_id}`.
+pub fn cif_text(lattice: &Lattice) -> Result<String, Box<dyn Error>> {
+    let cell = lattice_matrix(lattice)?;
+    let frac_coords = fractional_coordinates(lattice)?;
+    let (a, b, c): (Vector3<f64>, Vector3<f64>, Vector3<f64>) = (
+        cell.column(0).into(),
+        cell.column(1).into(),
+        cell.column(2).into(),
+    );
+    let angle = |u: &Vector3<f64>, v: &Vector3<f64>| u.angle(v).to_degrees();
+    let mut lines: Vec<String> = vec![
+        format!("data_{}", lattice.lattice_name()),
+        "_symmetry_space_group_name_H-M   'P 1'".to_string(),
+        "_symmetry_Int_Tables_number      1".to_string(),
+        format!("_cell_length_a                   {:.6}", a.norm()),
+        format!("_cell_length_b                   {:.6}", b.norm()),
+        format!("_cell_length_c                   {:.6}", c.norm()),
+        format!("_cell_angle_alpha                {:.6}", angle(&b, &c)),
+        format!("_cell_angle_beta                 {:.6}", angle(&a, &c)),
+        format!("_cell_angle_gamma                {:.6}", angle(&a, &b)),
+        String::new(),
+        "loop_".to_string(),
+        "_symmetry_equiv_pos_as_xyz".to_string(),
+        "  'x, y, z'".to_string(),
+        String::new(),
+        "loop_".to_string(),
+        "_atom_site_label".to_string(),
+        "_atom_site_type_symbol".to_string(),
+        "_atom_site_fract_x".to_string(),
+        "_atom_site_fract_y".to_string(),
+        "_atom_site_fract_z".to_string(),
+        "_atom_site_occupancy".to_string(),
+    ];
+    lattice
+        .atoms_vec()
+        .iter()
+        .zip(frac_coords.iter())
+        .for_each(|(atom, frac)| {
+            lines.push(format!(
+                "{:<8}{:<4}{:14.8}{:14.8}{:14.8}  1.0",
+                format!("{}{}", atom.element_name(), atom.atom_id()),
+                atom.element_name(),
+                frac.x,
+                frac.y,
+                frac.z
+            ))
+        });
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+#[test]
+fn test_structure_export() {
+    use castep_model_generator_backend::lattice::LatticeVectors;
+    use nalgebra::{Matrix3, Point3};
+
+    let atoms = vec![
+        Atom::new("C".to_string(), 6, Point3::new(0.0, 0.0, 5.0), 1),
+        Atom::new("Fe".to_string(), 26, Point3::new(5.0, 0.0, 5.0), 2),
+        Atom::new("C".to_string(), 6, Point3::new(0.0, 5.0, 5.0), 3),
+    ];
+    let cell = Matrix3::new(10.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 20.0);
+    let lattice = Lattice::new("GDY_Fe".to_string(), Some(LatticeVectors::new(cell)), atoms);
+    let poscar = poscar_text(&lattice, PoscarCoordinates::Direct).unwrap();
+    let lines: Vec<&str> = poscar.lines().collect();
+    assert_eq!(
+        lines[5].split_whitespace().collect::<Vec<&str>>(),
+        ["C", "Fe"]
+    );
+    assert_eq!(
+        lines[6].split_whitespace().collect::<Vec<&str>>(),
+        ["2", "1"]
+    );
+    // Both carbons come before iron.
+    assert!(lines[9].trim_start().starts_with("0.000"));
+    assert!(lines[10].trim_start().starts_with("0.500"));
+    let extxyz = extxyz_text(&lattice).unwrap();
+    assert!(extxyz.contains("Lattice=\"10.0000000000 0.0000000000"));
+    let cif = cif_text(&lattice).unwrap();
+    assert!(cif.contains("_cell_length_c                   20.000000"));
+    assert!(cif.contains("_cell_angle_gamma                90.000000"));
+    assert!(cif.contains("Fe2"));
+}
@@ -0,0 +1,517 @@
+use std::{error::Error, fmt::Display, fs, path::Path};
+
+use castep_model_generator_backend::{
+    atom::Atom,
+    lattice::{Lattice, LatticeVectors},
+    parser::msi_parser::parse_lattice,
+};
+use nalgebra::{Matrix3, Point3, Vector3};
+
+use crate::element_selection::element_by_symbol;
+
+pub const BOHR_TO_ANGSTROM: f64 = 0.529177210903;
+
+#[derive(Debug, Clone)]
+pub struct ParseStructureError(pub String);
+
+impl Display for ParseStructureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse structure: {}", self.0)
+    }
+}
+
+impl Error for ParseStructureError {}
+
+fn parse_error(msg: String) -> Box<dyn Error> {
+    Box::new(ParseStructureError(msg))
+}
+
+/**
+Read a base model from `.msi`, `.cif`, `.cell` or POSCAR (`POSCAR`, `CONTCAR`, `*.vasp`).
+The lattice is named after the file stem. Atoms read from the other formats get the
+ids 1..N in file order, so `substitution_sites` and `coord_sites` stay valid between runs.
+*/
+pub fn read_lattice(filename: &str) -> Result<Lattice, Box<dyn Error>> {
+    let path = Path::new(filename);
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let file_name = path
+        .file_name()
+        .map(|file| file.to_string_lossy().to_uppercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "msi" => parse_lattice(filename),
+        "cif" => parse_cif(&name, &fs::read_to_string(path)?),
+        "cell" => parse_cell(&name, &fs::read_to_string(path)?),
+        "vasp" | "poscar" => parse_poscar(&name, &fs::read_to_string(path)?),
+        _ if file_name.starts_with("POSCAR") || file_name.starts_with("CONTCAR") => {
+            parse_poscar(&name, &fs::read_to_string(path)?)
+        }
+        _ => Err(parse_error(format!(
+            "{}: unknown format, expected .msi, .cif, .cell or POSCAR",
+            filename
+        ))),
+    }
+}
+
+/// Lattice vectors as columns from the cell parameters, `a` along x and `b` in the xy plane.
+pub fn cell_from_parameters(
+    a: f64,
+    b: f64,
+    c: f64,
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+) -> Matrix3<f64> {
+    let (alpha, beta, gamma) = (alpha.to_radians(), beta.to_radians(), gamma.to_radians());
+    let cx = c * beta.cos();
+    let cy = c * (alpha.cos() - beta.cos() * gamma.cos()) / gamma.sin();
+    let cz = (c * c - cx * cx - cy * cy).sqrt();
+    Matrix3::from_columns(&[
+        Vector3::new(a, 0.0, 0.0),
+        Vector3::new(b * gamma.cos(), b * gamma.sin(), 0.0),
+        Vector3::new(cx, cy, cz),
+    ])
+}
+
+/// Build the lattice from (element, cartesian position) in file order, with ids 1..N.
+pub fn build_lattice(
+    name: &str,
+    cell: Matrix3<f64>,
+    sites: Vec<(String, Point3<f64>)>,
+) -> Result<Lattice, Box<dyn Error>> {
+    let atoms = sites
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (symbol, xyz))| -> Result<Atom, Box<dyn Error>> {
+            let element = element_by_symbol(&symbol)?;
+            Ok(Atom::new(
+                symbol,
+                element.atomic_number,
+                xyz,
+                idx as u32 + 1,
+            ))
+        })
+        .collect::<Result<Vec<Atom>, Box<dyn Error>>>()?;
+    Ok(Lattice::new(
+        name.to_string(),
+        Some(LatticeVectors::new(cell)),
+        atoms,
+    ))
+}
+
+/// Number of a CIF value, without its standard uncertainty, e.g. `14.52(3)`.
+fn cif_number(value: &str) -> Result<f64, Box<dyn Error>> {
+    let number = value.split('(').next().unwrap_or(value);
+    number
+        .parse::<f64>()
+        .map_err(|_| parse_error(format!("invalid CIF number {}", value)))
+}
+
+/// Element of an atom site label such as `Fe1` or `C12a`.
+fn symbol_from_label(label: &str) -> String {
+    let letters: String = label
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .take(2)
+        .collect();
+    let mut chars = letters.chars();
+    let first = chars.next().map(|c| c.to_ascii_uppercase());
+    let second = chars.next().map(|c| c.to_ascii_lowercase());
+    match (first, second) {
+        (Some(f), Some(s)) if element_by_symbol(&format!("{}{}", f, s)).is_ok() => {
+            format!("{}{}", f, s)
+        }
+        (Some(f), _) => f.to_string(),
+        _ => String::new(),
+    }
+}
+
+/**
+Read a CIF in P1 (one site per atom), as written by Materials Studio or `cif_text`.
+Symmetry-expanded structures with several symmetry operations are rejected.
+*/
+pub fn parse_cif(name: &str, text: &str) -> Result<Lattice, Box<dyn Error>> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let value_of = |key: &str| -> Result<f64, Box<dyn Error>> {
+        let line = lines
+            .iter()
+            .find(|line| line.split_whitespace().next() == Some(key))
+            .ok_or_else(|| parse_error(format!("{} missing in CIF", key)))?;
+        cif_number(line.split_whitespace().nth(1).unwrap_or_default())
+    };
+    let cell = cell_from_parameters(
+        value_of("_cell_length_a")?,
+        value_of("_cell_length_b")?,
+        value_of("_cell_length_c")?,
+        value_of("_cell_angle_alpha")?,
+        value_of("_cell_angle_beta")?,
+        value_of("_cell_angle_gamma")?,
+    );
+    let mut sites: Vec<(String, Point3<f64>)> = vec![];
+    let mut idx = 0;
+    while idx < lines.len() {
+        if !lines[idx].eq_ignore_ascii_case("loop_") {
+            idx += 1;
+            continue;
+        }
+        idx += 1;
+        let mut headers: Vec<&str> = vec![];
+        while idx < lines.len() && lines[idx].starts_with('_') {
+            headers.push(lines[idx]);
+            idx += 1;
+        }
+        let mut rows: Vec<Vec<&str>> = vec![];
+        while idx < lines.len()
+            && !lines[idx].starts_with('_')
+            && !lines[idx].eq_ignore_ascii_case("loop_")
+            && !lines[idx].starts_with("data_")
+        {
+            rows.push(lines[idx].split_whitespace().collect());
+            idx += 1;
+        }
+        let column = |key: &str| headers.iter().position(|h| *h == key);
+        let symop_column = column("_symmetry_equiv_pos_as_xyz")
+            .or_else(|| column("_space_group_symop_operation_xyz"));
+        if symop_column.is_some() && rows.len() > 1 {
+            return Err(parse_error(format!(
+                "{}: only P1 CIF files are supported, found {} symmetry operations",
+                name,
+                rows.len()
+            )));
+        }
+        let (Some(x_col), Some(y_col), Some(z_col)) = (
+            column("_atom_site_fract_x"),
+            column("_atom_site_fract_y"),
+            column("_atom_site_fract_z"),
+        ) else {
+            continue;
+        };
+        let label_col = column("_atom_site_label");
+        let symbol_col = column("_atom_site_type_symbol");
+        for row in rows.iter() {
+            if row.len() < headers.len() {
+                return Err(parse_error(format!(
+                    "{}: truncated atom site {:?}",
+                    name, row
+                )));
+            }
+            let symbol = match (symbol_col, label_col) {
+                (Some(col), _) => symbol_from_label(row[col]),
+                (None, Some(col)) => symbol_from_label(row[col]),
+                (None, None) => {
+                    return Err(parse_error(format!("{}: atom sites without labels", name)))
+                }
+            };
+            let frac = Vector3::new(
+                cif_number(row[x_col])?,
+                cif_number(row[y_col])?,
+                cif_number(row[z_col])?,
+            );
+            sites.push((symbol, Point3::from(cell * frac)));
+        }
+    }
+    if sites.is_empty() {
+        return Err(parse_error(format!("{}: no atom sites in CIF", name)));
+    }
+    build_lattice(name, cell, sites)
+}
+
+/// Read a VASP 5 POSCAR/CONTCAR, with the element symbols line.
+pub fn parse_poscar(name: &str, text: &str) -> Result<Lattice, Box<dyn Error>> {
+    let lines: Vec<&str> = text.lines().collect();
+    let invalid = |msg: &str| parse_error(format!("{}: {}", name, msg));
+    let numbers = |line: &str| -> Result<Vec<f64>, Box<dyn Error>> {
+        line.split_whitespace()
+            .take(3)
+            .map(|x| {
+                x.parse::<f64>()
+                    .map_err(|_| parse_error(format!("{}: invalid number {}", name, x)))
+            })
+            .collect()
+    };
+    if lines.len() < 8 {
+        return Err(invalid("POSCAR too short"));
+    }
+    let scale: f64 = lines[1]
+        .split_whitespace()
+        .next()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| invalid("invalid scaling factor"))?;
+    let vectors: Vec<Vector3<f64>> = lines[2..5]
+        .iter()
+        .map(|line| match numbers(line)?.as_slice() {
+            [x, y, z, ..] => Ok(Vector3::new(*x, *y, *z)),
+            _ => Err(invalid("lattice vector with fewer than 3 components")),
+        })
+        .collect::<Result<Vec<Vector3<f64>>, Box<dyn Error>>>()?;
+    let mut cell = Matrix3::from_columns(&vectors);
+    // A negative scaling factor is the cell volume.
+    let factor = if scale < 0.0 {
+        (-scale / cell.determinant().abs()).cbrt()
+    } else {
+        scale
+    };
+    cell *= factor;
+    let symbols: Vec<&str> = lines[5].split_whitespace().collect();
+    if symbols.iter().any(|s| s.parse::<u32>().is_ok()) {
+        return Err(invalid("VASP 4 POSCAR without element symbols"));
+    }
+    let counts: Vec<usize> = lines[6]
+        .split_whitespace()
+        .map(|x| x.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| invalid("invalid atom counts"))?;
+    if counts.len() != symbols.len() {
+        return Err(invalid("element symbols and counts do not match"));
+    }
+    let mut line_idx = 7;
+    if lines[line_idx].trim_start().starts_with(['S', 's']) {
+        line_idx += 1;
+    }
+    let cartesian = lines
+        .get(line_idx)
+        .map(|line| line.trim_start().starts_with(['C', 'c', 'K', 'k']))
+        .ok_or_else(|| invalid("missing coordinate mode"))?;
+    line_idx += 1;
+    let total: usize = counts.iter().sum();
+    if lines.len() < line_idx + total {
+        return Err(invalid("fewer positions than atoms"));
+    }
+    let species = symbols
+        .iter()
+        .zip(counts.iter())
+        .flat_map(|(symbol, &count)| std::iter::repeat_n(symbol.to_string(), count));
+    let sites = lines[line_idx..line_idx + total]
+        .iter()
+        .zip(species)
+        .map(
+            |(line, symbol)| -> Result<(String, Point3<f64>), Box<dyn Error>> {
+                let v = match numbers(line)?.as_slice() {
+                    [x, y, z, ..] => Vector3::new(*x, *y, *z),
+                    _ => return Err(invalid("position with fewer than 3 coordinates")),
+                };
+                let xyz = if cartesian { v * factor } else { cell * v };
+                Ok((symbol, Point3::from(xyz)))
+            },
+        )
+        .collect::<Result<Vec<(String, Point3<f64>)>, Box<dyn Error>>>()?;
+    build_lattice(name, cell, sites)
+}
+
+/// Lines of `%BLOCK {name}`, without the units line, matched case-insensitively.
+fn cell_block<'a>(lines: &[&'a str], name: &str) -> Option<(Vec<&'a str>, f64)> {
+    let start = lines.iter().position(|line| {
+        let mut words = line.split_whitespace();
+        words
+            .next()
+            .is_some_and(|w| w.eq_ignore_ascii_case("%BLOCK"))
+            && words.next().is_some_and(|w| w.eq_ignore_ascii_case(name))
+    })?;
+    let mut block: Vec<&str> = lines[start + 1..]
+        .iter()
+        .take_while(|line| {
+            !line
+                .split_whitespace()
+                .next()
+                .is_some_and(|w| w.eq_ignore_ascii_case("%ENDBLOCK"))
+        })
+        .copied()
+        .collect();
+    let mut unit = 1.0;
+    if let Some(first) = block.first() {
+        match first.trim().to_lowercase().as_str() {
+            "ang" => {
+                block.remove(0);
+            }
+            "bohr" | "a0" => {
+                unit = BOHR_TO_ANGSTROM;
+                block.remove(0);
+            }
+            "nm" => {
+                unit = 10.0;
+                block.remove(0);
+            }
+            _ => (),
+        }
+    }
+    Some((block, unit))
+}
+
+/// Read a CASTEP `.cell` with `LATTICE_CART` or `LATTICE_ABC`, and `POSITIONS_FRAC` or `POSITIONS_ABS`.
+pub fn parse_cell(name: &str, text: &str) -> Result<Lattice, Box<dyn Error>> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|line| line.split(['!', '#']).next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let invalid = |msg: &str| parse_error(format!("{}: {}", name, msg));
+    let number = |x: &str| -> Result<f64, Box<dyn Error>> {
+        x.parse::<f64>()
+            .map_err(|_| invalid(&format!("invalid number {}", x)))
+    };
+    let numbers = |line: &str| -> Result<Vec<f64>, Box<dyn Error>> {
+        line.split_whitespace().map(number).collect()
+    };
+    let cell = if let Some((block, unit)) = cell_block(&lines, "LATTICE_CART") {
+        let vectors = block
+            .iter()
+            .take(3)
+            .map(|line| match numbers(line)?.as_slice() {
+                [x, y, z, ..] => Ok(Vector3::new(*x, *y, *z) * unit),
+                _ => Err(invalid("incomplete LATTICE_CART")),
+            })
+            .collect::<Result<Vec<Vector3<f64>>, Box<dyn Error>>>()?;
+        if vectors.len() < 3 {
+            return Err(invalid("incomplete LATTICE_CART"));
+        }
+        Matrix3::from_columns(&vectors)
+    } else if let Some((block, unit)) = cell_block(&lines, "LATTICE_ABC") {
+        let (lengths, angles) = match block.as_slice() {
+            [lengths, angles, ..] => (numbers(lengths)?, numbers(angles)?),
+            _ => return Err(invalid("incomplete LATTICE_ABC")),
+        };
+        match (lengths.as_slice(), angles.as_slice()) {
+            ([a, b, c, ..], [alpha, beta, gamma, ..]) => {
+                cell_from_parameters(a * unit, b * unit, c * unit, *alpha, *beta, *gamma)
+            }
+            _ => return Err(invalid("incomplete LATTICE_ABC")),
+        }
+    } else {
+        return Err(invalid("no lattice block in .cell"));
+    };
+    let (block, unit, fractional) = match cell_block(&lines, "POSITIONS_FRAC") {
+        Some((block, _)) => (block, 1.0, true),
+        None => {
+            let (block, unit) = cell_block(&lines, "POSITIONS_ABS")
+                .ok_or_else(|| parse_error(format!("{}: no positions block in .cell", name)))?;
+            (block, unit, false)
+        }
+    };
+    let sites = block
+        .iter()
+        .map(|line| -> Result<(String, Point3<f64>), Box<dyn Error>> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            // Species may carry a label, e.g. `Fe:1`; trailing keywords such as `SPIN=` are ignored.
+            let (species, v) = match words.as_slice() {
+                [species, x, y, z, ..] => {
+                    (species, Vector3::new(number(x)?, number(y)?, number(z)?))
+                }
+                _ => return Err(invalid(&format!("invalid position {}", line))),
+            };
+            let symbol = species.split(':').next().unwrap_or_default().to_string();
+            let xyz = if fractional { cell * v } else { v * unit };
+            Ok((symbol, Point3::from(xyz)))
+        })
+        .collect::<Result<Vec<(String, Point3<f64>)>, Box<dyn Error>>>()?;
+    build_lattice(name, cell, sites)
+}
+
+#[cfg(test)]
+#[test]
+fn test_structure_import() {
+    let cif = "data_test
+_symmetry_space_group_name_H-M   'P 1'
+_cell_length_a   10.0
+_cell_length_b   10.0
+_cell_length_c   20.0(2)
+_cell_angle_alpha  90
+_cell_angle_beta   90
+_cell_angle_gamma  120
+loop_
+_symmetry_equiv_pos_as_xyz
+  'x, y, z'
+loop_
+_atom_site_label
+_atom_site_type_symbol
+_atom_site_fract_x
+_atom_site_fract_y
+_atom_site_fract_z
+C1  C  0.0 0.0 0.25
+Fe2 Fe 0.5 0.0 0.25
+";
+    let lattice = parse_cif("test", cif).unwrap();
+    assert_eq!(lattice.atoms_vec().len(), 2);
+    let fe = &lattice.atoms_vec()[1];
+    assert_eq!(
+        (fe.element_name(), fe.atom_id(), fe.element_id()),
+        ("Fe", 2, 26)
+    );
+    assert!((fe.xyz().x - 5.0).abs() < 1e-8 && (fe.xyz().z - 5.0).abs() < 1e-8);
+    let poscar = "test
+1.0
+10.0 0.0 0.0
+0.0 10.0 0.0
+0.0 0.0 20.0
+C Fe
+2 1
+Direct
+0.0 0.0 0.25
+0.0 0.5 0.25
+0.5 0.0 0.25
+";
+    let lattice = parse_poscar("test", poscar).unwrap();
+    let ids: Vec<u32> = lattice
+        .atoms_vec()
+        .iter()
+        .map(|atom| atom.atom_id())
+        .collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(lattice.atoms_vec()[2].element_name(), "Fe");
+    assert!((lattice.atoms_vec()[1].xyz().y - 5.0).abs() < 1e-8);
+    let cell = "%BLOCK LATTICE_CART
+   10.0 0.0 0.0
+   0.0 10.0 0.0
+   0.0 0.0 20.0
+%ENDBLOCK LATTICE_CART
+%block positions_frac
+   C    0.0 0.0 0.25
+  Fe    0.5 0.0 0.25 SPIN=  2.0000000000
+%endblock positions_frac
+";
+    let lattice = parse_cell("test", cell).unwrap();
+    assert_eq!(lattice.atoms_vec()[1].element_name(), "Fe");
+    assert!((lattice.atoms_vec()[1].xyz().x - 5.0).abs() < 1e-8);
+
+    // Truncated inputs are reported, not indexed out of bounds.
+    let truncated_poscar = poscar.replace("0.5 0.0 0.25", "0.5 0.0");
+    assert!(parse_poscar("test", &truncated_poscar).is_err());
+    assert!(parse_poscar("test", &poscar.replace("0.0 10.0 0.0", "0.0 10.0")).is_err());
+    assert!(parse_poscar(
+        "test",
+        &poscar.lines().take(9).collect::<Vec<_>>().join("\n")
+    )
+    .is_err());
+    assert!(parse_cif(
+        "test",
+        &cif.replace("Fe2 Fe 0.5 0.0 0.25", "Fe2 Fe 0.5 0.0")
+    )
+    .is_err());
+    assert!(parse_cif(
+        "test",
+        &cif.replace("_cell_length_c   20.0(2)", "_cell_length_c")
+    )
+    .is_err());
+    let abc = "%BLOCK LATTICE_ABC
+   10.0 10.0
+   90 90 120
+%ENDBLOCK LATTICE_ABC
+%BLOCK POSITIONS_FRAC
+   C    0.0 0.0 0.25
+%ENDBLOCK POSITIONS_FRAC
+";
+    assert!(parse_cell("test", abc).is_err());
+    assert!(parse_cell("test", &abc.replace("10.0 10.0", "10.0 10.0 20.0")).is_ok());
+    assert!(parse_cell("test", &cell.replace("0.0 10.0 0.0", "0.0 10.0")).is_err());
+    assert!(parse_cell("test", &cell.replace("0.5 0.0 0.25 SPIN", "0.5 0.0 SPIN")).is_err());
+}
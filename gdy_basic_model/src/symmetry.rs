@@ -0,0 +1,456 @@
+use std::{error::Error, fmt::Display};
+
+use castep_model_generator_backend::{
+    atom::{Atom, AtomArray},
+    lattice::Lattice,
+};
+use nalgebra::{Matrix3, Vector3};
+use serde::Deserialize;
+
+use crate::{
+    editor::gdy_tri_editor::MissingAtomIdError, project_config::SubstitutionSite,
+    seed_export::lattice_matrix,
+};
+
+/// Settings of the symmetry-aware deduplication of site assignments.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SymmetrySettings {
+    deduplicate: bool,
+    /// Tolerance of distance comparisons, in Angstrom.
+    tolerance: f64,
+    /// Radius of the neighbour shell compared between equivalent sites, and of the search for
+    /// reference atoms fixing the orientation of a candidate operation, in Angstrom.
+    env_cutoff: f64,
+}
+
+impl Default for SymmetrySettings {
+    fn default() -> Self {
+        Self {
+            deduplicate: true,
+            tolerance: 0.1,
+            env_cutoff: 3.5,
+        }
+    }
+}
+
+impl SymmetrySettings {
+    pub fn deduplicate(&self) -> bool {
+        self.deduplicate
+    }
+
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    pub fn env_cutoff(&self) -> f64 {
+        self.env_cutoff
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SingularCellError(pub String);
+
+impl Display for SingularCellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Lattice vectors of {} are singular", self.0)
+    }
+}
+
+impl Error for SingularCellError {}
+
+/// Lattice vectors (as columns) with their inverse, for minimum image vectors.
+#[derive(Debug, Clone, Copy)]
+pub struct PeriodicCell {
+    cell: Matrix3<f64>,
+    to_frac: Matrix3<f64>,
+}
+
+impl PeriodicCell {
+    /// `name` identifies the lattice in the error of a singular `cell`.
+    pub fn new(cell: Matrix3<f64>, name: &str) -> Result<Self, SingularCellError> {
+        let to_frac = cell
+            .try_inverse()
+            .ok_or_else(|| SingularCellError(name.to_string()))?;
+        Ok(Self { cell, to_frac })
+    }
+
+    pub fn from_lattice(lattice: &Lattice) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(lattice_matrix(lattice)?, lattice.lattice_name())?)
+    }
+
+    pub fn cell(&self) -> &Matrix3<f64> {
+        &self.cell
+    }
+
+    /// Shortest periodic image of the vector from `a` to `b`.
+    pub fn minimum_image_vector(&self, a: &Vector3<f64>, b: &Vector3<f64>) -> Vector3<f64> {
+        let frac = (self.to_frac * (b - a)).map(|x| x - x.round());
+        self.cell * frac
+    }
+
+    /// Distance between two cartesian points under the minimum image convention.
+    pub fn minimum_image_distance(&self, a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+        self.minimum_image_vector(a, b).norm()
+    }
+}
+
+/// All permutations of `0..n`, identity first.
+fn permutations(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![vec![]];
+    }
+    permutations(n - 1)
+        .into_iter()
+        .flat_map(|perm| -> Vec<Vec<usize>> {
+            (0..n)
+                .rev()
+                .map(|pos| {
+                    let mut new_perm = perm.clone();
+                    new_perm.insert(pos, n - 1);
+                    new_perm
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Rank of the span of `vectors`, ignoring extents below `tol` (Angstrom).
+fn span_rank(vectors: &[Vector3<f64>], tol: f64) -> usize {
+    let moments = vectors
+        .iter()
+        .fold(Matrix3::zeros(), |acc: Matrix3<f64>, v| {
+            acc + v * v.transpose()
+        });
+    moments
+        .symmetric_eigenvalues()
+        .iter()
+        .filter(|&&ev| ev > tol * tol)
+        .count()
+}
+
+/// Unit vector along the direction of least extent of `vectors`.
+fn least_extent_direction(vectors: &[Vector3<f64>]) -> Vector3<f64> {
+    let moments = vectors
+        .iter()
+        .fold(Matrix3::zeros(), |acc: Matrix3<f64>, v| {
+            acc + v * v.transpose()
+        });
+    let eigen = moments.symmetric_eigen();
+    let idx = eigen.eigenvalues.imin();
+    eigen.eigenvectors.column(idx).into_owned()
+}
+
+/**
+Orthogonal matrices `R` with `R p = q` for the pairs of (source, target) vectors.
+The best fit in the least squares sense (Kabsch, reflections allowed); when the sources
+only span a plane, the fit mirrored through that plane is given too.
+*/
+fn fitted_rotations(
+    sources: &[Vector3<f64>],
+    targets: &[Vector3<f64>],
+    tol: f64,
+) -> Vec<Matrix3<f64>> {
+    let h = sources
+        .iter()
+        .zip(targets.iter())
+        .fold(Matrix3::zeros(), |acc: Matrix3<f64>, (p, q)| {
+            acc + p * q.transpose()
+        });
+    let svd = h.svd(true, true);
+    let (Some(u), Some(v_t)) = (svd.u, svd.v_t) else {
+        return vec![];
+    };
+    let rotation = v_t.transpose() * u.transpose();
+    let mut rotations = vec![rotation];
+    if span_rank(sources, tol) == 2 {
+        let normal = least_extent_direction(sources);
+        rotations.push(rotation * (Matrix3::identity() - 2.0 * normal * normal.transpose()));
+    }
+    rotations
+        .into_iter()
+        .filter(|rotation| {
+            sources
+                .iter()
+                .zip(targets.iter())
+                .all(|(p, q)| (rotation * p - q).norm() <= tol)
+        })
+        .collect()
+}
+
+/**
+Permutations of the substituted sites induced by a symmetry operation of the whole structure.
+A permutation `p` moves the metal of site `i` to site `p[i]`. It is a candidate when
+all site-site distances are preserved and every site has the same neighbour shell
+(element and distance of the non-substituted atoms) as its image.
+A candidate is accepted when a rotation or reflection `R` maps site `i` onto site `p[i]`,
+`x -> s_p[0] + R (x - s_0)`, and, under periodic boundary conditions, maps the lattice onto
+itself and every other atom onto an atom of the same element within `tolerance`.
+`R` is fitted on the sites and on reference atoms around site 0 within `env_cutoff`, tried
+against every atom of the same element at the same distance from site `p[0]`.
+When the sites and the reference atoms are collinear the orientation is not fixed and
+some operations can be missed, which only leaves more models to compute.
+
+Only assignments generated from the `site_groups` pattern can be merged: with the shipped
+pattern (73 and 74 in group A, 75 in group B) a permuted assignment such as `B A A` is never
+generated, so nothing is skipped. Deduplication pays off with one group per site (`A B C`).
+*/
+#[derive(Debug, Clone)]
+pub struct SiteSymmetry {
+    permutations: Vec<Vec<usize>>,
+}
+
+impl SiteSymmetry {
+    pub fn detect(
+        lattice: &Lattice,
+        sites: &[SubstitutionSite],
+        settings: &SymmetrySettings,
+    ) -> Result<Self, Box<dyn Error>> {
+        let cell = PeriodicCell::from_lattice(lattice)?;
+        let site_atoms: Vec<&Atom> = sites
+            .iter()
+            .map(|site| {
+                lattice
+                    .atoms_vec()
+                    .get_atom_by_id(site.atom_id())
+                    .ok_or_else(|| MissingAtomIdError::new(site.atom_id(), lattice.lattice_name()))
+            })
+            .collect::<Result<Vec<&Atom>, MissingAtomIdError>>()?;
+        let site_ids: Vec<u32> = sites.iter().map(|site| site.atom_id()).collect();
+        let tol = settings.tolerance();
+        // Neighbour shell of each site: (element, distance), sorted by distance.
+        let shells: Vec<Vec<(&str, f64)>> = site_atoms
+            .iter()
+            .map(|site_atom| {
+                let mut shell: Vec<(&str, f64)> = lattice
+                    .atoms_vec()
+                    .iter()
+                    .filter(|atom| !site_ids.contains(&atom.atom_id()))
+                    .map(|atom| {
+                        (
+                            atom.element_name(),
+                            cell.minimum_image_distance(
+                                &site_atom.xyz().coords,
+                                &atom.xyz().coords,
+                            ),
+                        )
+                    })
+                    .filter(|(_, dist)| *dist <= settings.env_cutoff())
+                    .collect();
+                shell.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(b.0)));
+                shell
+            })
+            .collect();
+        let same_shell = |a: &[(&str, f64)], b: &[(&str, f64)]| -> bool {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b.iter())
+                    .all(|(x, y)| x.0 == y.0 && (x.1 - y.1).abs() <= tol)
+        };
+        let site_dist = |i: usize, j: usize| -> f64 {
+            cell.minimum_image_distance(&site_atoms[i].xyz().coords, &site_atoms[j].xyz().coords)
+        };
+        let n = sites.len();
+        let host_atoms: Vec<(&str, Vector3<f64>)> = lattice
+            .atoms_vec()
+            .iter()
+            .filter(|atom| !site_ids.contains(&atom.atom_id()))
+            .map(|atom| (atom.element_name(), atom.xyz().coords))
+            .collect();
+        let site_xyz: Vec<Vector3<f64>> = site_atoms.iter().map(|atom| atom.xyz().coords).collect();
+        let permutations = permutations(n)
+            .into_iter()
+            .filter(|perm| {
+                (0..n).all(|i| same_shell(&shells[i], &shells[perm[i]]))
+                    && (0..n).all(|i| {
+                        (i + 1..n)
+                            .all(|j| (site_dist(i, j) - site_dist(perm[i], perm[j])).abs() <= tol)
+                    })
+                    && maps_structure(&cell, &site_xyz, &host_atoms, perm, settings)
+            })
+            .collect();
+        Ok(Self { permutations })
+    }
+
+    pub fn permutations(&self) -> &[Vec<usize>] {
+        self.permutations.as_ref()
+    }
+
+    /// Lexicographically smallest arrangement equivalent to `site_elements`.
+    /// Two assignments are symmetry-equivalent if and only if their keys are equal.
+    pub fn canonical_key(&self, site_elements: &[&str]) -> Vec<String> {
+        self.permutations
+            .iter()
+            .map(|perm| {
+                let mut permuted = vec![String::new(); site_elements.len()];
+                site_elements
+                    .iter()
+                    .enumerate()
+                    .for_each(|(i, elm)| permuted[perm[i]] = elm.to_string());
+                permuted
+            })
+            .min()
+            .unwrap_or_else(|| site_elements.iter().map(|elm| elm.to_string()).collect())
+    }
+}
+
+/**
+Whether a rotation or reflection maps site `i` onto site `perm[i]` and the structure onto itself,
+see `SiteSymmetry`.
+*/
+fn maps_structure(
+    cell: &PeriodicCell,
+    site_xyz: &[Vector3<f64>],
+    host_atoms: &[(&str, Vector3<f64>)],
+    perm: &[usize],
+    settings: &SymmetrySettings,
+) -> bool {
+    if site_xyz.is_empty() {
+        return true;
+    }
+    let tol = settings.tolerance();
+    let origin = site_xyz[0];
+    let image_origin = site_xyz[perm[0]];
+    let mut sources: Vec<Vector3<f64>> = site_xyz
+        .iter()
+        .map(|xyz| cell.minimum_image_vector(&origin, xyz))
+        .collect();
+    let site_targets: Vec<Vector3<f64>> = perm
+        .iter()
+        .map(|&j| cell.minimum_image_vector(&image_origin, &site_xyz[j]))
+        .collect();
+    // Reference atoms around site 0, nearest first, until the orientation is fixed.
+    let mut nearby: Vec<(&str, Vector3<f64>)> = host_atoms
+        .iter()
+        .map(|(element, xyz)| (*element, cell.minimum_image_vector(&origin, xyz)))
+        .filter(|(_, v)| v.norm() <= settings.env_cutoff())
+        .collect();
+    nearby.sort_by(|a, b| a.1.norm().total_cmp(&b.1.norm()));
+    let mut references: Vec<(&str, Vector3<f64>)> = vec![];
+    for (element, v) in nearby {
+        let rank = span_rank(&sources, tol);
+        if rank == 3 {
+            break;
+        }
+        let mut extended = sources.clone();
+        extended.push(v);
+        if span_rank(&extended, tol) > rank {
+            sources = extended;
+            references.push((element, v));
+        }
+    }
+    // Every choice of images of the reference atoms.
+    let reference_images: Vec<Vec<Vector3<f64>>> = references
+        .iter()
+        .map(|(element, v)| {
+            host_atoms
+                .iter()
+                .filter(|(other, _)| other == element)
+                .map(|(_, xyz)| cell.minimum_image_vector(&image_origin, xyz))
+                .filter(|w| (w.norm() - v.norm()).abs() <= tol)
+                .collect()
+        })
+        .collect();
+    let target_sets = reference_images
+        .iter()
+        .fold(vec![site_targets], |sets, images| {
+            sets.iter()
+                .flat_map(|set| {
+                    images.iter().map(move |w| {
+                        let mut extended = set.clone();
+                        extended.push(*w);
+                        extended
+                    })
+                })
+                .collect()
+        });
+    let maps_lattice = |rotation: &Matrix3<f64>| -> bool {
+        cell.cell()
+            .column_iter()
+            .all(|axis| cell.minimum_image_distance(&Vector3::zeros(), &(rotation * axis)) <= tol)
+    };
+    let maps_atoms = |rotation: &Matrix3<f64>| -> bool {
+        host_atoms.iter().all(|(element, xyz)| {
+            let image = image_origin + rotation * cell.minimum_image_vector(&origin, xyz);
+            host_atoms.iter().any(|(other, other_xyz)| {
+                other == element && cell.minimum_image_distance(&image, other_xyz) <= tol
+            })
+        })
+    };
+    target_sets.iter().any(|targets| {
+        fitted_rotations(&sources, targets, tol)
+            .iter()
+            .any(|rotation| maps_lattice(rotation) && maps_atoms(rotation))
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn test_permutations() {
+    let perms = permutations(3);
+    assert_eq!(perms.len(), 6);
+    assert_eq!(perms[0], vec![0, 1, 2]);
+    let cell = PeriodicCell::new(
+        Matrix3::from_diagonal(&Vector3::new(10.0, 10.0, 10.0)),
+        "cubic",
+    )
+    .unwrap();
+    let dist =
+        cell.minimum_image_distance(&Vector3::new(0.5, 0.0, 0.0), &Vector3::new(9.5, 0.0, 0.0));
+    assert!((dist - 1.0).abs() < 1e-10);
+    assert!(PeriodicCell::new(Matrix3::zeros(), "flat").is_err());
+
+    let lattice = castep_model_generator_backend::parser::msi_parser::parse_lattice(
+        "./resources/GDY_tri.msi",
+    )
+    .unwrap();
+    let sites: Vec<SubstitutionSite> = serde_yaml::from_str(
+        "[{name: M1, atom_id: 73, group: A}, {name: M2, atom_id: 74, group: B}, {name: M3, atom_id: 75, group: C}]",
+    )
+    .unwrap();
+    let symmetry = SiteSymmetry::detect(&lattice, &sites, &SymmetrySettings::default()).unwrap();
+    assert_eq!(symmetry.permutations()[0], vec![0, 1, 2]);
+    // The three metal sites of the triangle are equivalent: every arrangement of three
+    // distinct metals is one class, and the two-metal patterns `A A B` fall into one class each.
+    let keys = |arrangements: &[[&str; 3]]| -> std::collections::HashSet<Vec<String>> {
+        arrangements
+            .iter()
+            .map(|elements| symmetry.canonical_key(elements))
+            .collect()
+    };
+    let abc = keys(&[
+        ["Fe", "Co", "Ni"],
+        ["Fe", "Ni", "Co"],
+        ["Co", "Fe", "Ni"],
+        ["Co", "Ni", "Fe"],
+        ["Ni", "Fe", "Co"],
+        ["Ni", "Co", "Fe"],
+    ]);
+    assert_eq!(symmetry.permutations().len(), 6);
+    assert_eq!(abc.len(), 1);
+    assert_eq!(
+        keys(&[["Fe", "Fe", "Co"], ["Fe", "Co", "Fe"], ["Co", "Fe", "Fe"]]).len(),
+        1
+    );
+
+    // Two sites with the same neighbour shell, swapped by a translation of half the cell,
+    // until an atom beyond `env_cutoff` breaks the operation.
+    let pair_sites: Vec<SubstitutionSite> = serde_yaml::from_str(
+        "[{name: M1, atom_id: 1, group: A}, {name: M2, atom_id: 2, group: B}]",
+    )
+    .unwrap();
+    let mut atoms = vec![
+        ("Fe".to_string(), nalgebra::Point3::new(5.0, 10.0, 10.0)),
+        ("Fe".to_string(), nalgebra::Point3::new(15.0, 10.0, 10.0)),
+        ("O".to_string(), nalgebra::Point3::new(5.0, 12.0, 10.0)),
+        ("O".to_string(), nalgebra::Point3::new(15.0, 12.0, 10.0)),
+    ];
+    let cubic = Matrix3::from_diagonal(&Vector3::new(20.0, 20.0, 20.0));
+    let pair = crate::structure_import::build_lattice("pair", cubic, atoms.clone()).unwrap();
+    let symmetry = SiteSymmetry::detect(&pair, &pair_sites, &SymmetrySettings::default()).unwrap();
+    assert_eq!(symmetry.permutations(), [vec![0, 1], vec![1, 0]]);
+    atoms.push(("N".to_string(), nalgebra::Point3::new(5.0, 5.0, 10.0)));
+    let broken = crate::structure_import::build_lattice("broken", cubic, atoms).unwrap();
+    let symmetry =
+        SiteSymmetry::detect(&broken, &pair_sites, &SymmetrySettings::default()).unwrap();
+    assert_eq!(symmetry.permutations(), [vec![0, 1]]);
+}
@@ -0,0 +1,226 @@
+use std::{collections::HashSet, error::Error, fmt::Display};
+
+use castep_model_generator_backend::lattice::Lattice;
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use crate::{
+    element_selection::element_by_symbol,
+    seed_export::{fractional_coordinates, lattice_matrix},
+};
+
+/// Covalent radii in Angstrom for Z = 1..96 (Cordero et al., Dalton Trans. 2008, low-spin Mn/Fe/Co).
+const COVALENT_RADII: [f64; 96] = [
+    0.31, 0.28, 1.28, 0.96, 0.84, 0.76, 0.71, 0.66, 0.57, 0.58, 1.66, 1.41, 1.21, 1.11, 1.07, 1.05,
+    1.02, 1.06, 2.03, 1.76, 1.70, 1.60, 1.53, 1.39, 1.39, 1.32, 1.26, 1.24, 1.32, 1.22, 1.22, 1.20,
+    1.19, 1.20, 1.20, 1.16, 2.20, 1.95, 1.90, 1.75, 1.64, 1.54, 1.47, 1.46, 1.42, 1.39, 1.45, 1.44,
+    1.42, 1.39, 1.39, 1.38, 1.39, 1.40, 2.44, 2.15, 2.07, 2.04, 2.03, 2.01, 1.99, 1.98, 1.98, 1.96,
+    1.94, 1.92, 1.92, 1.89, 1.90, 1.87, 1.87, 1.75, 1.70, 1.62, 1.51, 1.44, 1.41, 1.36, 1.36, 1.32,
+    1.45, 1.46, 1.48, 1.40, 1.50, 1.50, 2.60, 2.21, 2.15, 2.06, 2.00, 1.96, 1.90, 1.87, 1.80, 1.69,
+];
+
+pub fn covalent_radius(symbol: &str) -> Option<f64> {
+    let element = element_by_symbol(symbol).ok()?;
+    COVALENT_RADII
+        .get(element.atomic_number.checked_sub(1)? as usize)
+        .copied()
+}
+
+/// Settings of the geometric checks, from the `validation` entry of `project.yaml`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ValidationSettings {
+    enabled: bool,
+    /// Two atoms are too close below `min_distance_factor * (r_cov(a) + r_cov(b))`.
+    min_distance_factor: f64,
+    /// Do not write models failing the checks.
+    skip_invalid: bool,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_distance_factor: 0.7,
+            skip_invalid: false,
+        }
+    }
+}
+
+impl ValidationSettings {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn min_distance_factor(&self) -> f64 {
+        self.min_distance_factor
+    }
+
+    pub fn skip_invalid(&self) -> bool {
+        self.skip_invalid
+    }
+}
+
+/// One failed check of a model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    TooClose {
+        atom_ids: (u32, u32),
+        distance: f64,
+        minimum: f64,
+    },
+    OutsideCell {
+        atom_id: u32,
+        frac: Vector3<f64>,
+    },
+    DuplicateId {
+        atom_id: u32,
+    },
+    NoRadius {
+        element: String,
+    },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::TooClose {
+                atom_ids,
+                distance,
+                minimum,
+            } => write!(
+                f,
+                "atoms {} and {} are {:.3} A apart (minimum {:.3} A)",
+                atom_ids.0, atom_ids.1, distance, minimum
+            ),
+            ValidationIssue::OutsideCell { atom_id, frac } => write!(
+                f,
+                "atom {} is outside the cell at ({:.4}, {:.4}, {:.4})",
+                atom_id, frac.x, frac.y, frac.z
+            ),
+            ValidationIssue::DuplicateId { atom_id } => {
+                write!(f, "atom id {} is duplicated", atom_id)
+            }
+            ValidationIssue::NoRadius { element } => {
+                write!(f, "no covalent radius for element {}", element)
+            }
+        }
+    }
+}
+
+/**
+Check the minimum interatomic distances against the covalent radii over the 27 nearest
+periodic images, the fractional coordinates of every atom and the uniqueness of the atom ids.
+# Returns:
+- Every issue found; empty for a sound model.
+*/
+pub fn validate_lattice(
+    lattice: &Lattice,
+    settings: &ValidationSettings,
+) -> Result<Vec<ValidationIssue>, Box<dyn Error>> {
+    let mut issues: Vec<ValidationIssue> = vec![];
+    let atoms = lattice.atoms_vec();
+    let mut seen: HashSet<u32> = HashSet::new();
+    atoms.iter().for_each(|atom| {
+        if !seen.insert(atom.atom_id()) {
+            issues.push(ValidationIssue::DuplicateId {
+                atom_id: atom.atom_id(),
+            })
+        }
+    });
+    let cell = lattice_matrix(lattice)?;
+    let frac_coords = fractional_coordinates(lattice)?;
+    let tol = 1e-6;
+    atoms
+        .iter()
+        .zip(frac_coords.iter())
+        .for_each(|(atom, frac)| {
+            if frac.iter().any(|&x| x < -tol || x >= 1.0 + tol) {
+                issues.push(ValidationIssue::OutsideCell {
+                    atom_id: atom.atom_id(),
+                    frac: frac.coords,
+                })
+            }
+        });
+    let mut radii: Vec<f64> = vec![];
+    for atom in atoms.iter() {
+        match covalent_radius(atom.element_name()) {
+            Some(radius) => radii.push(radius),
+            None => {
+                issues.push(ValidationIssue::NoRadius {
+                    element: atom.element_name().to_string(),
+                });
+                return Ok(issues);
+            }
+        }
+    }
+    let images: Vec<Vector3<f64>> = (-1..=1)
+        .flat_map(|i| (-1..=1).flat_map(move |j| (-1..=1).map(move |k| (i, j, k))))
+        .map(|(i, j, k)| cell * Vector3::new(i as f64, j as f64, k as f64))
+        .collect();
+    for i in 0..atoms.len() {
+        for j in i + 1..atoms.len() {
+            let frac_diff = (frac_coords[j] - frac_coords[i]).map(|x| x - x.round());
+            let diff = cell * frac_diff;
+            let distance = images
+                .iter()
+                .map(|image| (diff + image).norm())
+                .fold(f64::INFINITY, f64::min);
+            let minimum = settings.min_distance_factor() * (radii[i] + radii[j]);
+            if distance < minimum {
+                issues.push(ValidationIssue::TooClose {
+                    atom_ids: (atoms[i].atom_id(), atoms[j].atom_id()),
+                    distance,
+                    minimum,
+                })
+            }
+        }
+    }
+    Ok(issues)
+}
+
+/// Issues of `lattice` as text, for the manifest; empty when the checks are disabled.
+pub fn model_issues(
+    lattice: &Lattice,
+    settings: &ValidationSettings,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if !settings.enabled() {
+        return Ok(vec![]);
+    }
+    Ok(validate_lattice(lattice, settings)?
+        .iter()
+        .map(|issue| issue.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+#[test]
+fn test_validate_lattice() {
+    use castep_model_generator_backend::{atom::Atom, lattice::LatticeVectors};
+    use nalgebra::{Matrix3, Point3};
+
+    let cell = Matrix3::new(10.0, 0.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 20.0);
+    let atoms = vec![
+        Atom::new("C".to_string(), 6, Point3::new(0.2, 5.0, 5.0), 1),
+        // 0.7 A from atom 1 through the periodic boundary along a.
+        Atom::new("C".to_string(), 6, Point3::new(9.5, 5.0, 5.0), 2),
+        Atom::new("Fe".to_string(), 26, Point3::new(5.0, 5.0, 25.0), 3),
+        Atom::new("O".to_string(), 8, Point3::new(5.0, 2.0, 5.0), 3),
+    ];
+    let lattice = Lattice::new("test".to_string(), Some(LatticeVectors::new(cell)), atoms);
+    let issues = validate_lattice(&lattice, &ValidationSettings::default()).unwrap();
+    assert_eq!(issues.len(), 3);
+    assert_eq!(issues[0], ValidationIssue::DuplicateId { atom_id: 3 });
+    assert!(matches!(
+        issues[1],
+        ValidationIssue::OutsideCell { atom_id: 3, .. }
+    ));
+    match &issues[2] {
+        ValidationIssue::TooClose {
+            atom_ids, distance, ..
+        } => {
+            assert_eq!(*atom_ids, (1, 2));
+            assert!((distance - 0.7).abs() < 1e-8);
+        }
+        issue => panic!("unexpected issue {}", issue),
+    }
+}